@@ -0,0 +1,400 @@
+use std::io::{Read, Write, Error, ErrorKind, BufWriter};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use num_bigint::{BigInt, Sign};
+
+use crate::evaluator::{Config, Flush, Eof};
+use crate::parser::match_brackets;
+use crate::streams::*;
+
+// Cells hold signed integers of unlimited size here, so none of the rules in
+// parser/helper.rs which rely on wrapping arithmetic can be used.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Op {
+    Add(BigInt),
+    Move(isize),
+    Input,
+    Output,
+    Loop(Vec<Op>),
+
+    // A loop which only adds constants to the cells around the counter and
+    // changes the counter by a step of 1 or -1. It stops after |counter|
+    // iterations if the counter starts out with the opposite sign of the
+    // step. Otherwise it never does, so the original body is run instead.
+    Linear(isize, Vec<(isize, BigInt)>, Vec<Op>),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BigState {
+    pub tape: VecDeque<BigInt>,
+    pub index: isize,
+    pub origin: isize,
+    pub steps: u64,
+}
+
+impl BigState {
+    pub fn cell(&mut self, offset: isize) -> &mut BigInt {
+        let mut target = self.index + offset;
+
+        while target < 0 {
+            self.tape.push_front(BigInt::default());
+            self.index += 1;
+            self.origin += 1;
+            target += 1;
+        }
+
+        while target >= self.tape.len() as isize {
+            self.tape.push_back(BigInt::default());
+        }
+
+        &mut self.tape[target as usize]
+    }
+
+    pub fn dump<W: Write>(&self, output: &mut W) -> Result<(), Error> {
+        let last = self.tape.iter().rposition(|x| x.sign() != Sign::NoSign)
+            .map_or(self.index, |x| (x as isize).max(self.index));
+
+        writeln!(output, "# pointer {:+}", self.index - self.origin)?;
+
+        if self.origin > 0 {
+            writeln!(output, "@{}", -self.origin)?;
+        }
+
+        let cells: Vec<String> = (0..=last)
+            .map(|x| self.tape.get(x as usize).map_or("0".to_string(), BigInt::to_string))
+            .collect();
+
+        for chunk in cells.chunks(16) {
+            writeln!(output, "{}", chunk.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn loop_op(body: Vec<Op>) -> Op {
+    let mut offset = 0;
+    let mut adds: Vec<(isize, BigInt)> = Vec::new();
+
+    for op in body.iter() {
+        match op {
+            Op::Add(val) => match adds.iter_mut().find(|x| x.0 == offset) {
+                Some(entry) => entry.1 += val,
+                None => adds.push((offset, val.clone())),
+            },
+
+            Op::Move(off) => offset += off,
+            _ => return Op::Loop(body),
+        }
+    }
+
+    let counter = adds.iter().position(|x| x.0 == 0).map(|k| adds.remove(k).1);
+
+    let step = match counter {
+        Some(val) if offset == 0 && val == BigInt::from(1) => 1,
+        Some(val) if offset == 0 && val == BigInt::from(-1) => -1,
+        _ => return Op::Loop(body),
+    };
+
+    adds.retain(|x| x.1.sign() != Sign::NoSign);
+    Op::Linear(step, adds, body)
+}
+
+fn parse_recursive(code: &[u8], ptr: &mut usize) -> Vec<Op> {
+    let mut prog = Vec::new();
+
+    while let Some(&byte) = code.get(*ptr) {
+        *ptr += 1;
+
+        match byte {
+            b'+' | b'-' => {
+                let val = if byte == b'+' { 1 } else { -1 };
+
+                match prog.last_mut() {
+                    Some(Op::Add(sum)) => *sum += val,
+                    _ => prog.push(Op::Add(BigInt::from(val))),
+                }
+
+                if prog.last() == Some(&Op::Add(BigInt::default())) {
+                    prog.pop();
+                }
+            },
+
+            b'>' | b'<' => {
+                let off = if byte == b'>' { 1 } else { -1 };
+
+                match prog.last_mut() {
+                    Some(Op::Move(sum)) => *sum += off,
+                    _ => prog.push(Op::Move(off)),
+                }
+
+                if prog.last() == Some(&Op::Move(0)) {
+                    prog.pop();
+                }
+            },
+
+            b',' => prog.push(Op::Input),
+            b'.' => prog.push(Op::Output),
+
+            b'[' => {
+                let body = parse_recursive(code, ptr);
+                prog.push(loop_op(body));
+            },
+
+            b']' => break,
+            _ => (),
+        }
+    }
+
+    prog
+}
+
+// Unmatched brackets are rejected just like in the naive interpreter, so
+// that both agree on which programs can run at all.
+pub fn parse_big(code: &[u8]) -> Result<Vec<Op>, Error> {
+    match_brackets(code).map_err(|pos| Error::new(
+        ErrorKind::InvalidData,
+        format!("unmatched bracket at position {}", pos)
+    ))?;
+
+    let mut ptr = 0;
+    Ok(parse_recursive(code, &mut ptr))
+}
+
+fn read_value<R: Read>(
+    input: &mut Scanner<R>,
+    format: &Format
+) -> Result<Option<BigInt>, Error> {
+    match format.input {
+        Mode::Char => Ok(input.next_byte()?.map(BigInt::from)),
+
+        Mode::Utf8 => loop {
+            let chr = match input.next_char()? {
+                Some(chr) => chr,
+                None => return Ok(None),
+            };
+
+            match (chr, format.invalid) {
+                (char::REPLACEMENT_CHARACTER, Invalid::Skip) => continue,
+                (char::REPLACEMENT_CHARACTER, Invalid::Error) => return Err(invalid_char(chr)),
+                _ => return Ok(Some(BigInt::from(chr as u32))),
+            }
+        },
+
+        mode => {
+            let radix = mode.radix();
+
+            match input.next_number(radix)? {
+                Some(text) => Ok(BigInt::parse_bytes(text.as_bytes(), radix)),
+                None => Ok(None),
+            }
+        },
+    }
+}
+
+fn write_value<W: Write>(
+    output: &mut W,
+    value: &BigInt,
+    format: &Format
+) -> Result<(), Error> {
+    match format.output {
+        Mode::Char => output.write_all(&value.to_signed_bytes_le()[..1]),
+        Mode::Decimal => write!(output, "{} ", value),
+        Mode::Hex => write!(output, "{:x} ", value),
+
+        Mode::Utf8 => match u32::try_from(value).ok().and_then(char::from_u32) {
+            Some(chr) => write!(output, "{}", chr),
+
+            None => match format.invalid {
+                Invalid::Replace => write!(output, "{}", char::REPLACEMENT_CHARACTER),
+                Invalid::Skip => Ok(()),
+                Invalid::Error => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("cell value {} is not a valid code point", value)
+                )),
+            },
+        },
+    }
+}
+
+fn eval_loop<R,W>(
+    body: &[Op],
+    input: &mut Scanner<R>,
+    output: &mut W,
+    state: &mut BigState,
+    config: &Config,
+    format: &Format
+) -> Result<bool, Error>
+where R: Read, W: Write {
+    while state.cell(0).sign() != Sign::NoSign {
+        if state.steps >= config.limit {
+            return Ok(false);
+        }
+
+        state.steps += 1;
+
+        if !eval_recursive(body, input, output, state, config, format)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn eval_recursive<R,W>(
+    prog: &[Op],
+    input: &mut Scanner<R>,
+    output: &mut W,
+    state: &mut BigState,
+    config: &Config,
+    format: &Format
+) -> Result<bool, Error>
+where R: Read, W: Write {
+    for op in prog.iter() {
+        match op {
+            Op::Add(val) => *state.cell(0) += val,
+            Op::Move(off) => state.index += off,
+
+            Op::Input => {
+                if config.flush == Flush::Input {
+                    output.flush()?;
+                }
+
+                if let Some(value) = read_value(input, format)? {
+                    *state.cell(0) = value;
+                } else if config.eof != Eof::Unchanged {
+                    *state.cell(0) = BigInt::from(if config.eof == Eof::Max { -1 } else { 0 });
+                }
+            },
+
+            Op::Output => {
+                let value = state.cell(0);
+                write_value(output, value, format)?;
+
+                if config.flush == Flush::Newline && *value == BigInt::from(b'\n') {
+                    output.flush()?;
+                }
+            },
+
+            Op::Loop(body) => {
+                if !eval_loop(body, input, output, state, config, format)? {
+                    return Ok(false);
+                }
+            },
+
+            Op::Linear(step, adds, body) => {
+                let counter = state.cell(0).clone();
+
+                if counter.sign() == Sign::NoSign {
+                    continue;
+                }
+
+                if (counter.sign() == Sign::Minus) != (*step > 0) {
+                    if !eval_loop(body, input, output, state, config, format)? {
+                        return Ok(false);
+                    }
+
+                    continue;
+                }
+
+                if state.steps >= config.limit {
+                    return Ok(false);
+                }
+
+                state.steps += 1;
+                let count = counter * -step;
+
+                for (off, val) in adds.iter() {
+                    *state.cell(*off) += val * &count;
+                }
+
+                *state.cell(0) = BigInt::default();
+            },
+        }
+    }
+
+    Ok(true)
+}
+
+// Unlike eval_state, a paused program cannot be resumed later, since the
+// state files only hold 8 bit cells.
+pub fn eval_big<R,W>(
+    prog: &[Op],
+    input: R,
+    output: &mut W,
+    state: &mut BigState,
+    config: &Config,
+    format: &Format
+) -> Result<bool, Error>
+where R: Read, W: Write {
+    let mut input = Scanner::new(input);
+
+    let mut output = BufWriter::with_capacity(0x2000, output);
+    let done = eval_recursive(prog, &mut input, &mut output, state, config, format)?;
+    output.flush()?;
+    Ok(done)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::empty;
+
+    use crate::bignum::*;
+
+    fn decimal() -> Format {
+        Format {
+            output: Mode::Decimal,
+            ..Format::default()
+        }
+    }
+
+    #[test]
+    fn parse_linear_loops() {
+        let prog = parse_big(b"+[-]>[>++<+]<[>[-]<-]").unwrap();
+
+        assert_eq!(prog[1], Op::Linear(-1, vec![], vec![Op::Add(BigInt::from(-1))]));
+        assert!(matches!(&prog[3], Op::Linear(1, adds, _) if adds == &vec![(1, BigInt::from(2))]));
+        assert!(matches!(prog[5], Op::Loop(_)));
+    }
+
+    #[test]
+    fn parse_unmatched_brackets() {
+        assert!(parse_big(b"+[-").is_err());
+        assert!(parse_big(b"+]-").is_err());
+    }
+
+    #[test]
+    fn eval_without_wrapping() {
+        let code = b"++++++++++++++++++++[>++++++++++++++++++++<-]>.<-.>>--[+>+++<]>.";
+        let prog = parse_big(code).unwrap();
+        let mut state = BigState::default();
+        let mut output = Vec::new();
+        eval_big(&prog, empty(), &mut output, &mut state, &Config::default(), &decimal()).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "400 -1 6 ");
+    }
+
+    #[test]
+    fn eval_large_values() {
+        // doubles a cell a hundred times
+        let code = b">>++++++++++[-<<++++++++++>>]<+<[->[->++<]>[-<+>]<<]>.";
+        let prog = parse_big(code).unwrap();
+        let mut state = BigState::default();
+        let mut output = Vec::new();
+        eval_big(&prog, empty(), &mut output, &mut state, &Config::default(), &decimal()).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "1267650600228229401496703205376 ");
+    }
+
+    #[test]
+    fn eval_negative_clear_loop() {
+        let prog = parse_big(b"-[-]").unwrap();
+        let mut state = BigState::default();
+        let config = Config { limit: 1000, ..Config::default() };
+        let done = eval_big(&prog, empty(), &mut Vec::new(), &mut state, &config, &Format::default()).unwrap();
+
+        assert!(!done);
+        assert_eq!(state.steps, 1000);
+        assert_eq!(state.tape[0], BigInt::from(-1001));
+    }
+}
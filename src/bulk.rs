@@ -0,0 +1,192 @@
+use std::num::Wrapping;
+
+const ONES: u64 = 0x0101_0101_0101_0101;
+const HIGHS: u64 = 0x8080_8080_8080_8080;
+
+fn word(chunk: &[Wrapping<u8>]) -> u64 {
+    let mut bytes = [0u8; 8];
+
+    for (byte, cell) in bytes.iter_mut().zip(chunk.iter()) {
+        *byte = cell.0;
+    }
+
+    u64::from_le_bytes(bytes)
+}
+
+// Checks eight cells at once, since a byte of `x` is only 0 where a cell
+// holds the value and subtracting 1 from it is the only way to set its high
+// bit without it having been set before.
+fn holds(chunk: &[Wrapping<u8>], val: Wrapping<u8>) -> bool {
+    let x = word(chunk) ^ (ONES * val.0 as u64);
+    x.wrapping_sub(ONES) & !x & HIGHS != 0
+}
+
+pub fn find(cells: &[Wrapping<u8>], val: Wrapping<u8>) -> Option<usize> {
+    let mut chunks = cells.chunks_exact(8);
+
+    for (k, chunk) in chunks.by_ref().enumerate() {
+        if holds(chunk, val) {
+            return chunk.iter().position(|&x| x == val).map(|x| k * 8 + x);
+        }
+    }
+
+    let rest = chunks.remainder();
+    rest.iter().position(|&x| x == val).map(|x| cells.len() - rest.len() + x)
+}
+
+pub fn rfind(cells: &[Wrapping<u8>], val: Wrapping<u8>) -> Option<usize> {
+    let mut chunks = cells.rchunks_exact(8);
+
+    for (k, chunk) in chunks.by_ref().enumerate() {
+        if holds(chunk, val) {
+            let start = cells.len() - (k + 1) * 8;
+            return chunk.iter().rposition(|&x| x == val).map(|x| start + x);
+        }
+    }
+
+    chunks.remainder().iter().rposition(|&x| x == val)
+}
+
+fn inside(tape: &[Wrapping<u8>], index: isize) -> bool {
+    index >= 0 && index < tape.len() as isize
+}
+
+// Moves the index by the step until it reaches a cell with the value, or
+// until it leaves the tape. Everything beyond the tape is left to the
+// evaluator, which also knows how to read cells that do not exist yet.
+pub fn scan(tape: &[Wrapping<u8>], index: &mut isize, val: Wrapping<u8>, step: isize) {
+    if !inside(tape, *index) || step == 0 {
+        return;
+    }
+
+    let start = *index as usize;
+    let len = tape.len() as isize;
+
+    *index = match step {
+        1 => find(&tape[start..], val).map_or(len, |x| (start + x) as isize),
+        -1 => rfind(&tape[..=start], val).map_or(-1, |x| x as isize),
+
+        k if k > 0 => {
+            let found = tape[start..].iter().step_by(k as usize).position(|&x| x == val);
+            let count = (len - *index + k - 1) / k;
+            *index + found.map_or(count, |x| x as isize) * k
+        },
+
+        k => {
+            let found = tape[..=start].iter().rev().step_by(-k as usize).position(|&x| x == val);
+            let count = *index / -k + 1;
+            *index + found.map_or(count, |x| x as isize) * k
+        },
+    };
+}
+
+// Does the work of a `Fill` for as long as both the checked cells and the
+// written ones are on the tape. A write ahead of the index at a multiple of
+// the step would change a cell which is yet to be checked, so those fills
+// are left to the evaluator as well.
+pub fn fill(tape: &mut [Wrapping<u8>], index: &mut isize, off: isize, val: Wrapping<u8>, step: isize) {
+    if step == 0 || off != 0 && off % step == 0 && off / step > 0 {
+        return;
+    }
+
+    if !inside(tape, *index) || !inside(tape, *index + off) {
+        return;
+    }
+
+    let start = *index as usize;
+    let len = tape.len() as isize;
+
+    match step {
+        1 => {
+            let zero = find(&tape[start..], Wrapping(0)).map_or(len, |x| (start + x) as isize);
+            let end = zero.min(len - off);
+
+            tape[(*index + off) as usize..(end + off) as usize].fill(val);
+            *index = end;
+        },
+
+        -1 => {
+            let zero = rfind(&tape[..=start], Wrapping(0)).map_or(-1, |x| x as isize);
+            let end = zero.max(-off - 1);
+
+            tape[(end + 1 + off) as usize..=(*index + off) as usize].fill(val);
+            *index = end;
+        },
+
+        _ => {
+            while inside(tape, *index) && inside(tape, *index + off) && tape[*index as usize] != Wrapping(0) {
+                tape[(*index + off) as usize] = val;
+                *index += step;
+            }
+        },
+    }
+}
+
+// Adds the value to a number of cells which are the step apart, starting
+// with the first one.
+pub fn add_strided(tape: &mut [Wrapping<u8>], first: usize, step: isize, runs: usize, val: Wrapping<u8>) {
+    if step > 0 {
+        for cell in tape[first..].iter_mut().step_by(step as usize).take(runs) {
+            *cell += val;
+        }
+    } else {
+        for cell in tape[..=first].iter_mut().rev().step_by(-step as usize).take(runs) {
+            *cell += val;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::Wrapping;
+    use crate::bulk::*;
+
+    fn cells(bytes: &[u8]) -> Vec<Wrapping<u8>> {
+        bytes.iter().map(|&x| Wrapping(x)).collect()
+    }
+
+    #[test]
+    fn find_in_words() {
+        let tape = cells(b"abcdefghijklmnopqrstuvwxyz0123456789abcdefghijk");
+
+        for (pos, &byte) in b"abcdefghijklmnopqrstuvwxyz0123456789".iter().enumerate() {
+            let val = Wrapping(byte);
+            assert_eq!(find(&tape, val), tape.iter().position(|&x| x == val));
+            assert_eq!(rfind(&tape, val), tape.iter().rposition(|&x| x == val));
+            assert_eq!(find(&tape[pos..], val), Some(0));
+        }
+
+        assert_eq!(find(&tape, Wrapping(0)), None);
+        assert_eq!(rfind(&tape[..3], Wrapping(b'z')), None);
+    }
+
+    #[test]
+    fn scan_with_steps() {
+        let tape = cells(&[1, 0, 2, 3, 4, 0, 5, 6, 0, 7]);
+        let expected = [(2, 1, 5), (9, 1, 10), (3, 3, 12), (9, -1, 8), (9, -2, 5), (7, -3, 1), (6, -4, -2)];
+
+        for &(start, step, end) in expected.iter() {
+            let mut index = start;
+            scan(&tape, &mut index, Wrapping(0), step);
+            assert_eq!(index, end, "{} {}", start, step);
+        }
+    }
+
+    #[test]
+    fn fill_within_tape() {
+        let mut tape = cells(&[0, 1, 1, 1, 1, 0, 9]);
+        let mut index = 1;
+        fill(&mut tape, &mut index, -1, Wrapping(7), 1);
+        assert_eq!((index, tape.clone()), (5, cells(&[7, 7, 7, 7, 1, 0, 9])));
+
+        let mut tape = cells(&[1, 1, 1, 1, 1]);
+        let mut index = 2;
+        fill(&mut tape, &mut index, 2, Wrapping(0), -1);
+        assert_eq!((index, tape.clone()), (-1, cells(&[1, 1, 0, 0, 0])));
+
+        // the write at +2 ends the fill early, which only the evaluator does
+        let mut index = 0;
+        fill(&mut tape, &mut index, 2, Wrapping(0), 1);
+        assert_eq!(index, 0);
+    }
+}
@@ -0,0 +1,249 @@
+use std::io::{Write, Error};
+
+use crate::internal::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target {
+    C,
+}
+
+impl Target {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "c" => Some(Target::C),
+            _ => None,
+        }
+    }
+}
+
+// The tape grows in both directions just like in the evaluator, so the
+// generated code keeps the same `Touch` instructions and an index into a
+// buffer which gets moved when cells are added at the front.
+const C_PRELUDE: &str = "\
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static unsigned char *tape;
+static long size, i;
+
+static void grow(long front, long back) {
+    unsigned char *next = calloc(size + front + back, 1);
+
+    if (!next) {
+        perror(\"rbf\");
+        exit(1);
+    }
+
+    memcpy(next + front, tape, size);
+    free(tape);
+
+    tape = next;
+    size += front + back;
+    i += front;
+}
+
+// Leaves the cell alone if adding the step never makes it 0, since the loop
+// which follows never ends then.
+static unsigned char count(unsigned char *cell, unsigned char step) {
+    unsigned char c = *cell;
+    int n = 0;
+
+    while (c && n < 256) {
+        c += step;
+        n++;
+    }
+
+    if (c) {
+        return 0;
+    }
+
+    *cell = 0;
+    return n;
+}
+
+static void touch(long high, long low) {
+    if (i + low < 0 || i + high >= size) {
+        grow(i + low < 0 ? size - i - low : 0, i + high >= size ? i + high + 1 : 0);
+    }
+}
+
+int main(void) {
+    unsigned char r = 0;
+    int c;
+
+    size = 0x1000;
+    i = 0x400;
+    tape = calloc(size, 1);
+";
+
+fn cell(off: isize) -> String {
+    match off {
+        0 => "tape[i]".to_string(),
+        k if k < 0 => format!("tape[i - {}]", -k),
+        k => format!("tape[i + {}]", k),
+    }
+}
+
+fn shift(off: isize) -> String {
+    match off {
+        k if k < 0 => format!("i -= {};", -k),
+        k => format!("i += {};", k),
+    }
+}
+
+// Octal escapes are used since they end after three digits, unlike hex
+// escapes which would swallow any digits following them.
+fn c_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&x| match x {
+        b'"' | b'\\' | b'?' => format!("\\{}", x as char),
+        b' '..=b'~' => (x as char).to_string(),
+        _ => format!("\\{:03o}", x),
+    }).collect()
+}
+
+fn write_c_body<W: Write>(prog: &[IR], depth: usize, output: &mut W) -> Result<(), Error> {
+    let pad = "    ".repeat(depth);
+
+    for inst in prog.iter() {
+        match inst {
+            IR::Start => (),
+            IR::Touch(high, low) => writeln!(output, "{}touch({}, {});", pad, high, low)?,
+            IR::Set(off, val) => writeln!(output, "{}{} = {};", pad, cell(*off), val)?,
+            IR::Add(off, val) => writeln!(output, "{}{} += {};", pad, cell(*off), val)?,
+            IR::Mul(off, val) => writeln!(output, "{}{} += r * {};", pad, cell(*off), val)?,
+            IR::Move(off) => writeln!(output, "{}{}", pad, shift(*off))?,
+
+            IR::Store(off) => {
+                writeln!(output, "{}r = {};", pad, cell(*off))?;
+                writeln!(output, "{}{} = 0;", pad, cell(*off))?;
+            },
+
+            IR::Count(off, step) => writeln!(output, "{}r = count(&{}, {});", pad, cell(*off), step)?,
+
+            IR::Loop(sub) => {
+                writeln!(output, "{}while (tape[i]) {{", pad)?;
+                write_c_body(sub, depth + 1, output)?;
+                writeln!(output, "{}}}", pad)?;
+            },
+
+            IR::FixedLoop(sub, off, _, _) => {
+                writeln!(output, "{}while ({}) {{", pad, cell(*off))?;
+                write_c_body(sub, depth + 1, output)?;
+                writeln!(output, "{}}}", pad)?;
+            },
+
+            IR::If(sub, off, _, _) => {
+                writeln!(output, "{}if ({}) {{", pad, cell(*off))?;
+                write_c_body(sub, depth + 1, output)?;
+                writeln!(output, "{}}}", pad)?;
+            },
+
+            // the evaluator reads cells beyond the tape as 0 here, so the
+            // cell has to exist before it can be compared
+            IR::Scan(val, step) => {
+                writeln!(output, "{}while (touch(0, 0), tape[i] != {}) {{", pad, val)?;
+                writeln!(output, "{}    {}", pad, shift(*step))?;
+                writeln!(output, "{}}}", pad)?;
+            },
+
+            IR::Walk(adds, step) => {
+                writeln!(output, "{}while (touch(0, 0), tape[i]) {{", pad)?;
+
+                for (off, val) in adds.iter() {
+                    writeln!(output, "{}    touch({}, {});", pad, off, off)?;
+                    writeln!(output, "{}    {} += {};", pad, cell(*off), val)?;
+                }
+
+                writeln!(output, "{}    {}", pad, shift(*step))?;
+                writeln!(output, "{}}}", pad)?;
+            },
+
+            IR::Fill(off, val, step) => {
+                writeln!(output, "{}while (touch(0, 0), tape[i]) {{", pad)?;
+                writeln!(output, "{}    touch({}, {});", pad, off, off)?;
+                writeln!(output, "{}    {} = {};", pad, cell(*off), val)?;
+                writeln!(output, "{}    {}", pad, shift(*step))?;
+                writeln!(output, "{}}}", pad)?;
+            },
+
+            IR::Input(off) => {
+                writeln!(output, "{}fflush(stdout);", pad)?;
+                writeln!(output, "{}c = getchar();", pad)?;
+                writeln!(output, "{}{} = c == EOF ? 0 : c;", pad, cell(*off))?;
+            },
+
+            IR::Output(off) => writeln!(output, "{}putchar({});", pad, cell(*off))?,
+
+            IR::Print(bytes) => {
+                writeln!(output, "{}fwrite(\"{}\", 1, {}, stdout);", pad, c_string(bytes), bytes.len())?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_c<W: Write>(prog: &[IR], output: &mut W) -> Result<(), Error> {
+    output.write_all(C_PRELUDE.as_bytes())?;
+    write_c_body(prog, 1, output)?;
+    writeln!(output, "\n    (void) r;\n    (void) c;\n    return 0;\n}}")
+}
+
+pub fn compile<W: Write>(prog: &[IR], target: Target, output: &mut W) -> Result<(), Error> {
+    match target {
+        Target::C => write_c(prog, output),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::*;
+    use crate::compile::*;
+
+    fn c_source(code: &[u8]) -> String {
+        let mut output = Vec::new();
+        compile(&parse(code), Target::C, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn compile_c_multiply() {
+        let text = c_source(b",[>++<-]>.");
+        let body: Vec<&str> = text.lines()
+            .skip_while(|x| !x.contains("tape = calloc"))
+            .skip(1)
+            .map(str::trim)
+            .collect();
+
+        assert_eq!(&body[..8], &[
+            "touch(1, 0);",
+            "fflush(stdout);",
+            "c = getchar();",
+            "tape[i] = c == EOF ? 0 : c;",
+            "r = tape[i];",
+            "tape[i] = 0;",
+            "tape[i + 1] += r * 2;",
+            "putchar(tape[i + 1]);",
+        ]);
+    }
+
+    #[test]
+    fn compile_c_nested_loops() {
+        let text = c_source(b"+[>[-<+>>]<<[<]>]");
+
+        assert!(text.contains("    while (tape[i]) {\n        touch(1, 0);\n        i += 1;\n"), "{}", text);
+        assert!(text.contains("        }\n        touch(0, -2);\n        i -= 2;\n"), "{}", text);
+        assert!(text.contains("while (touch(0, 0), tape[i] != 0) {\n            i -= 1;\n"), "{}", text);
+        assert_eq!(text.matches('{').count(), text.matches('}').count());
+    }
+
+    #[test]
+    fn compile_c_print() {
+        let mut output = Vec::new();
+        compile(&[IR::Print(b"a\"1\n\x002".to_vec())], Target::C, &mut output).unwrap();
+
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("fwrite(\"a\\\"1\\012\\0002\", 1, 6, stdout);"), "{}", text);
+    }
+}
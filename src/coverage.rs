@@ -0,0 +1,181 @@
+use std::io::{Write, Error};
+
+use crate::parser::*;
+use crate::evaluator::*;
+use crate::naive::*;
+
+pub struct Coverage {
+    code: Vec<u8>,
+    jumps: Vec<usize>,
+    hits: Vec<u64>,
+    iterations: Vec<u64>,
+}
+
+impl SourceObserver for Coverage {
+    fn command(&mut self, pos: usize, state: &State) {
+        self.hits[pos] += 1;
+
+        let nonzero = state.tape.get(state.index as usize).is_some_and(|x| x.0 != 0);
+
+        match self.code[pos] {
+            b'[' if nonzero => self.iterations[pos] += 1,
+            b']' if nonzero => self.iterations[self.jumps[pos]] += 1,
+            _ => (),
+        }
+    }
+}
+
+fn is_command(byte: u8) -> bool {
+    b"+-<>[].,".contains(&byte)
+}
+
+impl Coverage {
+    pub fn new(code: &[u8]) -> Self {
+        Coverage {
+            code: code.to_vec(),
+            jumps: match_brackets(code).unwrap_or_else(|_| vec![0; code.len()]),
+            hits: vec![0; code.len()],
+            iterations: vec![0; code.len()],
+        }
+    }
+
+    pub fn write_annotated<W: Write>(&self, output: &mut W) -> Result<(), Error> {
+        let mut start = 0;
+
+        for (number, line) in self.code.split(|&x| x == b'\n').enumerate() {
+            let first = start;
+            let range = first..first + line.len();
+            start = range.end + 1;
+
+            if first == self.code.len() && number > 0 {
+                break;
+            }
+
+            let commands: Vec<usize> = range.clone()
+                .filter(|&pos| is_command(self.code[pos]))
+                .collect();
+
+            let gutter = match commands.iter().map(|&pos| self.hits[pos]).max() {
+                None => "-".to_string(),
+                Some(0) => "#####".to_string(),
+                Some(hits) => hits.to_string(),
+            };
+
+            write!(output, "{:>9}:{:>5}:", gutter, number + 1)?;
+            output.write_all(line)?;
+            writeln!(output)?;
+
+            let missed = commands.iter().any(|&pos| self.hits[pos] == 0);
+            let partial = commands.iter().any(|&pos| self.hits[pos] > 0);
+
+            if missed && partial {
+                let markers: String = range.clone()
+                    .map(|pos| match self.hits[pos] {
+                        0 if is_command(self.code[pos]) => '^',
+                        _ if self.code[pos] == b'\t' => '\t',
+                        _ => ' ',
+                    })
+                    .collect();
+
+                writeln!(output, "{:>9}:{:>5}:{}", "", "", markers.trim_end())?;
+            }
+
+            for pos in range.filter(|&pos| self.code[pos] == b'[') {
+                writeln!(
+                    output,
+                    "{:>9}:{:>5}:loop {}:{} entered {} times, {} iterations",
+                    "", "",
+                    number + 1,
+                    pos - first + 1,
+                    self.hits[pos],
+                    self.iterations[pos],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn summary(&self) {
+        let mut lines = (0, 0);
+        let mut start = 0;
+
+        for line in self.code.split(|&x| x == b'\n') {
+            let range = start..start + line.len();
+            start = range.end + 1;
+
+            let mut commands = range.filter(|&pos| is_command(self.code[pos])).peekable();
+
+            if commands.peek().is_some() {
+                lines.1 += 1;
+
+                if commands.any(|pos| self.hits[pos] > 0) {
+                    lines.0 += 1;
+                }
+            }
+        }
+
+        let commands = (
+            (0..self.code.len()).filter(|&x| is_command(self.code[x]) && self.hits[x] > 0).count(),
+            (0..self.code.len()).filter(|&x| is_command(self.code[x])).count(),
+        );
+
+        let loops = (
+            (0..self.code.len()).filter(|&x| self.code[x] == b'[' && self.iterations[x] > 0).count(),
+            (0..self.code.len()).filter(|&x| self.code[x] == b'[').count(),
+        );
+
+        eprintln!("\ncoverage summary:");
+
+        for (name, (hit, found)) in [("lines", lines), ("commands", commands), ("loops", loops)].iter() {
+            let rate = if *found == 0 { 100.0 } else { 100.0 * *hit as f64 / *found as f64 };
+            let dots = ".".repeat(10 - name.len());
+            eprintln!("  {}{}: {:.1}% ({} of {} {})", name, dots, rate, hit, found, name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{empty, sink};
+
+    use crate::coverage::*;
+
+    fn annotate(code: &[u8]) -> String {
+        let mut state = State::default();
+        let mut coverage = Coverage::new(code);
+        run(code, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut coverage).unwrap();
+
+        let mut output = Vec::new();
+        coverage.write_annotated(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn coverage_loops() {
+        let code = b"++[>+<-]\n[-]>+ x";
+        let expected = concat!(
+            "        2:    1:++[>+<-]\n",
+            "         :     :loop 1:3 entered 1 times, 2 iterations\n",
+            "        1:    2:[-]>+ x\n",
+            "         :     : ^^\n",
+            "         :     :loop 2:1 entered 1 times, 0 iterations\n",
+        );
+
+        assert_eq!(annotate(code), expected);
+    }
+
+    #[test]
+    fn coverage_unreached_line() {
+        let code = b"comment\n[\n.\n]";
+        let expected = concat!(
+            "        -:    1:comment\n",
+            "        1:    2:[\n",
+            "         :     :loop 2:1 entered 1 times, 0 iterations\n",
+            "    #####:    3:.\n",
+            "    #####:    4:]\n",
+        );
+
+        assert_eq!(annotate(code), expected);
+    }
+}
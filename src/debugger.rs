@@ -0,0 +1,254 @@
+use std::io::{BufRead, Write, Error, stdout};
+use std::collections::BTreeSet;
+
+use crate::parser::*;
+use crate::evaluator::*;
+use crate::naive::*;
+use crate::repl::tape_window;
+
+const HELP: &str = "\
+step [N]      run N commands, 1 by default
+continue      run until the next breakpoint
+break L:C     stop after the command at line L, column C
+delete L:C    remove the breakpoint at line L, column C
+tape          print the cells around the pointer
+help          print this help message
+quit          stop the program";
+
+// Finds the first command at or after the given line and column.
+pub fn find_command(code: &[u8], line: usize, column: usize) -> Option<usize> {
+    let mut current = (1, 1);
+
+    for (pos, &byte) in code.iter().enumerate() {
+        if current >= (line, column) && b"+-<>[].,".contains(&byte) {
+            return Some(pos);
+        }
+
+        current = match byte {
+            b'\n' => (current.0 + 1, 1),
+            _ => (current.0, current.1 + 1),
+        };
+    }
+
+    None
+}
+
+fn parse_location(text: &str) -> Option<(usize, usize)> {
+    let mut split = text.splitn(2, ':');
+    let line = split.next()?.parse().ok()?;
+    let column = split.next().unwrap_or("1").parse().ok()?;
+    Some((line, column))
+}
+
+// Runs on the source level, so every command can be stopped at. Since the
+// observer is only called once a command has run, the debugger always stops
+// after a command rather than in front of it.
+pub struct Debugger<R: BufRead, W: Write> {
+    code: Vec<u8>,
+    commands: R,
+    output: W,
+    breakpoints: BTreeSet<usize>,
+    remaining: u64,
+    running: bool,
+    quit: bool,
+    error: Option<Error>,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    pub fn new(code: &[u8], commands: R, output: W) -> Self {
+        Self {
+            code: code.to_vec(),
+            commands,
+            output,
+            breakpoints: BTreeSet::new(),
+            remaining: 0,
+            running: false,
+            quit: false,
+            error: None,
+        }
+    }
+
+    fn location(&self, pos: usize) -> String {
+        let (line, column) = line_column(&self.code, pos);
+        format!("line {}, column {}", line, column)
+    }
+
+    fn breakpoint(&mut self, arg: &str, insert: bool) -> Result<(), Error> {
+        let found = parse_location(arg)
+            .and_then(|(line, column)| find_command(&self.code, line, column));
+
+        let pos = match found {
+            Some(pos) => pos,
+            None => return writeln!(self.output, "error: no command at {}", arg),
+        };
+
+        let changed = if insert {
+            self.breakpoints.insert(pos)
+        } else {
+            self.breakpoints.remove(&pos)
+        };
+
+        let what = match (insert, changed) {
+            (true, _) => "breakpoint",
+            (false, true) => "removed breakpoint",
+            (false, false) => "no breakpoint",
+        };
+
+        let location = self.location(pos);
+        writeln!(self.output, "{} at `{}`, {}", what, self.code[pos] as char, location)
+    }
+
+    // Reads commands until one of them continues the program.
+    fn prompt(&mut self, state: &State) -> Result<(), Error> {
+        loop {
+            write!(self.output, "(rbf) ")?;
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self.commands.read_line(&mut line)? == 0 {
+                self.quit = true;
+                return writeln!(self.output);
+            }
+
+            let mut split = line.trim().splitn(2, ' ');
+            let command = split.next().unwrap_or_default();
+            let arg = split.next().unwrap_or_default().trim();
+
+            match command {
+                "step" | "s" | "" => {
+                    self.remaining = if arg.is_empty() { 1 } else {
+                        match arg.parse() {
+                            Ok(count) => count,
+                            Err(_) => {
+                                writeln!(self.output, "error: invalid count {}", arg)?;
+                                continue;
+                            },
+                        }
+                    };
+
+                    self.running = false;
+                    return Ok(());
+                },
+
+                "continue" | "c" => {
+                    self.running = true;
+                    return Ok(());
+                },
+
+                "break" | "b" => self.breakpoint(arg, true)?,
+                "delete" | "d" => self.breakpoint(arg, false)?,
+                "tape" | "t" => writeln!(self.output, "{}", tape_window(state, 8))?,
+                "help" | "h" => writeln!(self.output, "{}", HELP)?,
+
+                "quit" | "q" => {
+                    self.quit = true;
+                    return Ok(());
+                },
+
+                _ => writeln!(self.output, "error: unknown command {}, type help for help", command)?,
+            }
+        }
+    }
+
+    fn stop(&mut self, pos: Option<usize>, state: &State) -> Result<(), Error> {
+        stdout().flush()?;
+
+        match pos {
+            Some(pos) => {
+                let location = self.location(pos);
+                writeln!(self.output, "stopped after `{}` at {}", self.code[pos] as char, location)?;
+            },
+            None => writeln!(self.output, "stopped at the start")?,
+        }
+
+        writeln!(self.output, "{}", tape_window(state, 4))?;
+        self.prompt(state)
+    }
+
+    // Gives the chance to set breakpoints before the first command runs.
+    pub fn start(&mut self, state: &State) -> Result<(), Error> {
+        self.stop(None, state)
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> SourceObserver for Debugger<R, W> {
+    fn command(&mut self, pos: usize, state: &State) {
+        if self.quit {
+            return;
+        }
+
+        self.remaining = self.remaining.saturating_sub(1);
+
+        let hit = self.breakpoints.contains(&pos);
+        if !hit && (self.running || self.remaining > 0) {
+            return;
+        }
+
+        if let Err(err) = self.stop(Some(pos), state) {
+            self.error = Some(err);
+            self.quit = true;
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.quit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{empty, sink};
+
+    use crate::debugger::*;
+
+    fn session(code: &[u8], commands: &str) -> (String, bool, State) {
+        let mut state = State::default();
+        let mut output = Vec::new();
+        let mut debugger = Debugger::new(code, commands.as_bytes(), &mut output);
+
+        debugger.start(&state).unwrap();
+        let done = run(code, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut debugger).unwrap();
+        debugger.finish().unwrap();
+
+        (String::from_utf8(output).unwrap(), done, state)
+    }
+
+    #[test]
+    fn find_command_by_location() {
+        let code = b"+ a\n  >[-]\n";
+        assert_eq!(find_command(code, 1, 1), Some(0));
+        assert_eq!(find_command(code, 1, 2), Some(6));
+        assert_eq!(find_command(code, 2, 4), Some(7));
+        assert_eq!(find_command(code, 3, 1), None);
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let (text, done, _) = session(b"+++\n>++", "break 2:2\ncontinue\nstep\ncontinue\n");
+        let stops: Vec<&str> = text.lines()
+            .filter_map(|x| x.trim_start_matches("(rbf) ").strip_prefix("stopped "))
+            .collect();
+
+        assert!(done);
+        assert_eq!(stops, vec![
+            "at the start",
+            "after `+` at line 2, column 2",
+            "after `+` at line 2, column 3",
+        ]);
+    }
+
+    #[test]
+    fn quit_stops_program() {
+        let (_, done, state) = session(b"+[+]", "step 3\nquit\n");
+
+        assert!(!done);
+        assert_eq!(state.tape[state.index as usize].0, 2);
+    }
+}
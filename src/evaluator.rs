@@ -1,99 +1,242 @@
-use std::io::{Read, Write, Error};
-use std::cmp::max;
+use std::io::{Read, Write, Error, ErrorKind, BufWriter};
+use std::fmt;
+use std::cmp::{max, min};
 use std::num::Wrapping;
 use std::collections::VecDeque;
-use std::iter::repeat;
+use std::iter::repeat_n;
 
 use crate::internal::*;
+use crate::bulk::*;
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct State {
+    pub tape: VecDeque<Wrapping<u8>>,
+    pub index: isize,
+    pub origin: isize,
+    pub position: Vec<usize>,
+    pub consumed: u64,
+    pub steps: u64,
+}
+
+impl State {
+    pub fn new(tape: VecDeque<Wrapping<u8>>, index: isize) -> Self {
+        State {
+            tape,
+            index,
+            origin: index,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flush {
+    Exit,
+    Newline,
+    Input,
+    Never,
+}
+
+impl Flush {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "exit" => Some(Flush::Exit),
+            "newline" => Some(Flush::Newline),
+            "input" => Some(Flush::Input),
+            "never" => Some(Flush::Never),
+            _ => None,
+        }
+    }
+}
+
+// What `,` stores once the input is used up.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Eof {
+    Zero,
+    Max,
+    Unchanged,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    pub limit: u64,
+    pub flush: Flush,
+    pub strict: bool,
+    pub eof: Eof,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            limit: u64::MAX,
+            flush: Flush::Input,
+            strict: false,
+            eof: Eof::Zero,
+        }
+    }
+}
+
+impl Eof {
+    pub fn value(self, cell: Wrapping<u8>) -> u8 {
+        match self {
+            Eof::Zero => 0,
+            Eof::Max => 255,
+            Eof::Unchanged => cell.0,
+        }
+    }
+}
+
+// Strict evaluation stops with this error as soon as a cell would leave the
+// range of a byte instead of wrapping around. Only the source level knows
+// the exact position of the offending command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Overflow {
+    pub cell: isize,
+    pub value: i32,
+    pub pos: Option<usize>,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cell {:+} would become {}", self.cell, self.value)
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+impl Overflow {
+    pub fn check(state: &State, target: isize, value: i32, pos: Option<usize>) -> Result<Wrapping<u8>, Error> {
+        if (0..=255).contains(&value) {
+            return Ok(Wrapping(value as u8));
+        }
+
+        let overflow = Overflow { cell: target - state.origin, value, pos };
+        Err(Error::new(ErrorKind::InvalidData, overflow))
+    }
+
+    pub fn find(err: &Error) -> Option<&Overflow> {
+        err.get_ref().and_then(|x| x.downcast_ref())
+    }
+}
 
 #[inline]
 fn touch_range(
-    tape : &mut VecDeque<Wrapping<u8>>,
+    state : &mut State,
     index : &mut isize,
     high : isize,
     low : isize
 ) {
-    let upper = *index as isize + high + 1;
-    let lower = *index as isize + low;
+    let upper = *index + high + 1;
+    let lower = *index + low;
 
-    let upper_diff = max(0, upper - tape.len() as isize) as usize;
+    let upper_diff = max(0, upper - state.tape.len() as isize) as usize;
     let lower_diff = max(0, -lower) as usize;
     let total_diff = upper_diff + lower_diff;
 
     if total_diff > 0 {
-        tape.extend(repeat(Wrapping(0u8)).take(total_diff));
-        tape.rotate_right(lower_diff);
+        state.tape.extend(repeat_n(Wrapping(0u8), total_diff));
+        state.tape.rotate_right(lower_diff);
 
         *index += lower_diff as isize;
+        state.origin += lower_diff as isize;
     }
 }
 
 #[inline]
-fn touch_cell(
-    tape : &mut VecDeque<Wrapping<u8>>,
+pub fn touch_cell(
+    state : &mut State,
     index : &mut isize,
     offset : isize
 ) -> isize {
-    let target = *index as isize + offset;
+    let target = *index + offset;
 
     if target < 0 {
         let n = -target as _;
-        tape.extend(repeat(Wrapping(0u8)).take(n));
-        tape.rotate_right(n);
+        state.tape.extend(repeat_n(Wrapping(0u8), n));
+        state.tape.rotate_right(n);
 
         *index -= target;
+        state.origin -= target;
         return 0;
     }
 
-    let diff = target - tape.len() as isize;
+    let diff = target - state.tape.len() as isize;
 
     if diff >= 0 {
-        let n = diff as _;
-        tape.extend(repeat(Wrapping(0u8)).take(n));
+        let n = diff as usize + 1;
+        state.tape.extend(repeat_n(Wrapping(0u8), n));
     }
 
     target
 }
 
+#[inline]
+fn strict_add(state: &mut State, target: isize, delta: i32) -> Result<(), Error> {
+    let value = state.tape[target as usize].0 as i32 + delta;
+    state.tape[target as usize] = Overflow::check(state, target, value, None)?;
+    Ok(())
+}
+
 macro_rules! cell {
-    (read, $tape:ident, $index:expr) => {
-        $tape.get($index as usize).unwrap_or(&Wrapping(0u8))
+    (read, $state:ident, $index:expr) => {
+        $state.tape.get($index as usize).unwrap_or(&Wrapping(0u8))
     };
 
-    (write, $tape:ident, $index:expr) => {
-        $tape.get_mut($index as usize).unwrap()
+    (write, $state:ident, $index:expr) => {
+        $state.tape.get_mut($index as usize).unwrap()
     };
 }
 
+pub trait Observer {
+    #[inline]
+    fn inst(&mut self, _inst: &IR, _state: &State) {}
+
+    #[inline]
+    fn iteration(&mut self, _inst: &IR, _state: &State) {}
+}
 
-pub fn eval_recursive<R,W>(
+impl Observer for () {}
+
+// Execution can only be paused at the head of a loop. The position of a
+// paused program is the path of instruction indices leading to that loop,
+// which is collected in reverse while unwinding.
+pub fn eval_recursive<R,W,O>(
     prog: &[IR],
     input: &mut R,
     output: &mut W,
-    tape: &mut VecDeque<Wrapping<u8>>,
-    mut index: isize,
-    buffer: &mut [u8; 1]
-) -> Result<isize, Error>
-where R: Read, W: Write {
+    state: &mut State,
+    resume: &[usize],
+    config: &Config,
+    observer: &mut O
+) -> Result<bool, Error>
+where R: Read, W: Write, O: Observer {
     let mut register = Wrapping(0u8);
+    let mut buffer = [0u8];
+    let mut index = state.index;
+
+    let start = resume.first().copied().unwrap_or_default();
 
-    for inst in prog.iter() {
+    for (pos, inst) in prog.iter().enumerate().skip(start) {
         match inst {
             IR::Touch(high, low) => {
-                touch_range(tape, &mut index, *high, *low);
+                touch_range(state, &mut index, *high, *low);
             },
 
             IR::Set(off, val) => {
-                *cell!(write, tape, index + off) = *val;
+                *cell!(write, state, index + off) = *val;
             },
 
-            IR::Add(off, val) => {
-                *cell!(write, tape, index + off) += *val;
+            IR::Add(off, val) => if config.strict {
+                strict_add(state, index + off, signed(*val))?;
+            } else {
+                *cell!(write, state, index + off) += *val;
             },
 
-            IR::Mul(off, val) => {
+            IR::Mul(off, val) => if config.strict {
+                strict_add(state, index + off, register.0 as i32 * signed(*val))?;
+            } else {
                 let term = *val * register;
-                *cell!(write, tape, index + off) += term;
+                *cell!(write, state, index + off) += term;
             }
 
             IR::Move(off) => {
@@ -101,108 +244,357 @@ where R: Read, W: Write {
             },
 
             IR::Store(off) => {
-                let cell = cell!(write, tape, index + off);
+                let cell = cell!(write, state, index + off);
 
                 register = *cell;
                 *cell = Wrapping(0u8);
             },
 
-            IR::Scan(val, step) => loop {
-                if *cell!(read, tape, index) == *val {
-                    break;
+            IR::Count(off, step) => {
+                let cell = cell!(write, state, index + off);
+
+                match trip_count(*cell, *step) {
+                    Some(count) => {
+                        register = count;
+                        *cell = Wrapping(0u8);
+                    },
+
+                    None => register = Wrapping(0u8),
                 }
+            },
 
-                index += *step;
+            IR::Scan(val, step) => {
+                // the bulk search does not check every cell it passes
+                if !config.strict || *val == Wrapping(0u8) {
+                    scan(state.tape.make_contiguous(), &mut index, *val, *step);
+                }
+
+                loop {
+                    if *cell!(read, state, index) == *val {
+                        break;
+                    }
+
+                    index += *step;
+
+                    // the original loop briefly subtracts the value it
+                    // scans for from every cell it passes
+                    if config.strict {
+                        let value = cell!(read, state, index).0 as i32 - signed(*val);
+                        Overflow::check(state, index, value, None)?;
+                    }
+                }
+            },
+
+            // the adds never change a cell before it is checked, so the end
+            // can be found first and the adds done in bulk afterwards
+            IR::Walk(adds, step) => {
+                let start = index;
+                scan(state.tape.make_contiguous(), &mut index, Wrapping(0u8), *step);
+
+                let runs = (index - start) / *step;
+
+                if runs > 0 {
+                    let back = start - index;
+
+                    for (off, _) in adds.iter() {
+                        let first = back + *off;
+                        let last = *off - *step;
+                        touch_range(state, &mut index, max(first, last), min(first, last));
+                    }
+
+                    let tape = state.tape.make_contiguous();
+
+                    for (off, val) in adds.iter() {
+                        add_strided(tape, (index + back + *off) as usize, *step, runs as usize, *val);
+                    }
+                }
             },
 
             IR::Fill(off, val, step) => loop {
-                if *cell!(read, tape, index) == Wrapping(0u8) {
+                fill(state.tape.make_contiguous(), &mut index, *off, *val, *step);
+
+                if *cell!(read, state, index) == Wrapping(0u8) {
                     break;
                 }
 
-                let target = touch_cell(tape, &mut index, *off);
-                *cell!(write, tape, target) = *val;
+                let target = touch_cell(state, &mut index, *off);
+                *cell!(write, state, target) = *val;
 
                 index += *step;
             },
 
             IR::Input(off) => {
-                if input.read_exact(buffer).is_err() {
-                    buffer[0] = 0u8;
+                if config.flush == Flush::Input {
+                    output.flush()?;
+                }
+
+                let cell = cell!(write, state, index + off);
+
+                match input.read_exact(&mut buffer) {
+                    Ok(()) => state.consumed += 1,
+                    Err(err) if err.kind() == ErrorKind::UnexpectedEof => buffer[0] = config.eof.value(*cell),
+                    Err(err) => return Err(err),
                 }
 
-                let val = Wrapping(buffer[0]);
-                *cell!(write, tape, index + off) = val;
+                *cell = Wrapping(buffer[0]);
             },
 
             IR::Output(off) => {
-                buffer[0] = cell!(read, tape, index + off).0;
-                output.write_all(buffer)?;
+                buffer[0] = cell!(read, state, index + off).0;
+                output.write_all(&buffer)?;
+
+                if config.flush == Flush::Newline && buffer[0] == b'\n' {
+                    output.flush()?;
+                }
+            },
+
+            IR::Print(bytes) => {
+                output.write_all(bytes)?;
+
+                if config.flush == Flush::Newline && bytes.contains(&b'\n') {
+                    output.flush()?;
+                }
             },
 
             IR::Loop(loop_prog) |
-            IR::FixedLoop(loop_prog, _, _) => loop {
-                if *cell!(read, tape, index) == Wrapping(0u8) {
-                    break;
+            IR::FixedLoop(loop_prog, _, _, _) |
+            IR::If(loop_prog, _, _, _) => {
+                // balanced loops check a cell next to the pointer instead of
+                // moving it there
+                let off = match inst {
+                    IR::FixedLoop(_, off, _, _) | IR::If(_, off, _, _) => *off,
+                    _ => 0,
+                };
+
+                state.index = index;
+
+                if pos == start && resume.len() > 1 {
+                    if !eval_recursive(loop_prog, input, output, state, &resume[1..], config, observer)? {
+                        state.position.push(pos);
+                        return Ok(false);
+                    }
+
+                    index = state.index;
                 }
 
-                index = eval_recursive(&loop_prog, input, output, tape, index, buffer)?;
+                loop {
+                    if *cell!(read, state, index + off) == Wrapping(0u8) {
+                        break;
+                    }
+
+                    if state.steps >= config.limit {
+                        state.position.push(pos);
+                        return Ok(false);
+                    }
+
+                    state.steps += 1;
+                    observer.iteration(inst, state);
+
+                    if !eval_recursive(loop_prog, input, output, state, &[], config, observer)? {
+                        state.position.push(pos);
+                        return Ok(false);
+                    }
+
+                    index = state.index;
+
+                    // the body of an if has cleared the cell already
+                    if let IR::If(_, _, _, _) = inst {
+                        break;
+                    }
+                }
             },
 
             _ => (),
         }
+
+        state.index = index;
+        observer.inst(inst, state);
     }
 
-    Ok(index)
+    state.index = index;
+    Ok(true)
 }
 
-pub fn eval<R,W>(
+pub fn eval_state<R,W,O>(
     prog: &[IR],
     input: &mut R,
     output: &mut W,
-    tape: &mut VecDeque<Wrapping<u8>>,
-    index: isize
-)
-where R: Read, W: Write {
-    let mut buffer = [0u8];
-    eval_recursive(prog, input, output, tape, index, &mut buffer).unwrap();
+    state: &mut State,
+    config: &Config,
+    observer: &mut O
+) -> Result<bool, Error>
+where R: Read, W: Write, O: Observer {
+    let resume = std::mem::take(&mut state.position);
+
+    let mut output = BufWriter::with_capacity(0x2000, output);
+    let done = eval_recursive(prog, input, &mut output, state, &resume, config, observer)?;
+    output.flush()?;
+
+    if done {
+        state.position = vec![prog.len()];
+    } else {
+        state.position.reverse();
+    }
+
+    Ok(done)
 }
 
 #[cfg(test)]
 mod test {
-    use std::collections::VecDeque;
     use std::io::{empty, sink};
 
     use crate::evaluator::*;
     use crate::parser::*;
+    use crate::naive::run;
+
+    fn eval<R: Read, W: Write>(prog: &[IR], input: &mut R, output: &mut W) -> State {
+        let mut state = State::default();
+        eval_state(prog, input, output, &mut state, &Config::default(), &mut ()).unwrap();
+        state
+    }
 
     #[test]
     fn eval_simple() {
         let code = b"+++>--<[>++++<-]++";
         let prog = parse(code);
-        let mut tape = VecDeque::new();
-        eval(&prog, &mut empty(), &mut sink(), &mut tape, 0);
-        assert_eq!(tape, vec![Wrapping(2), Wrapping(10)]);
+        let state = eval(&prog, &mut empty(), &mut sink());
+        assert_eq!(state.tape, vec![Wrapping(2), Wrapping(10)]);
+    }
+
+    #[test]
+    fn touch_cell_past_end() {
+        let mut state = State::new(VecDeque::from(vec![Wrapping(0u8); 4]), 0);
+        let mut index = 0;
+
+        let target = touch_cell(&mut state, &mut index, 4);
+        *state.tape.get_mut(target as usize).unwrap() = Wrapping(1);
+
+        assert_eq!((target, index), (4, 0));
+        assert_eq!(state.tape.len(), 5);
     }
 
     #[test]
     fn eval_hello() {
         let code = b"+[-->-[>>+>-----<<]<--<---]>-.>>>+.>>..+++[.>]<<<<.+++.------.<<-.>>>>+.";
         let prog = parse(code);
-        let mut tape = VecDeque::new();
         let mut output = Vec::new();
-        eval(&prog, &mut empty(), &mut output, &mut tape, 0);
+        eval(&prog, &mut empty(), &mut output);
         assert_eq!(output, b"Hello, World!");
     }
 
+    struct Recorder {
+        data: Vec<u8>,
+        flushes: Vec<usize>,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            self.flushes.push(self.data.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn eval_flush_policy() {
+        let prog = parse(b"++++++++++.>+++++[<++++++++++>-]<.,.");
+        let expected = [
+            (Flush::Exit, vec![3]),
+            (Flush::Newline, vec![1, 3]),
+            (Flush::Input, vec![2, 3]),
+            (Flush::Never, vec![3]),
+        ];
+
+        for (flush, flushes) in expected.iter() {
+            let mut output = Recorder { data: Vec::new(), flushes: Vec::new() };
+            let mut state = State::default();
+            let config = Config { flush: *flush, ..Config::default() };
+            eval_state(&prog, &mut empty(), &mut output, &mut state, &config, &mut ()).unwrap();
+
+            assert_eq!(output.data, b"\n<\0");
+            assert_eq!(&output.flushes, flushes);
+        }
+    }
+
+    #[test]
+    fn eval_strict_cells() {
+        let config = Config { strict: true, ..Config::default() };
+        let long = [&[b'+'; 200][..], &[b'-'; 200][..]].concat();
+
+        let cases = [
+            (&b"+[-]-"[..], Some((0, -1))),
+            (b"++++++++++++++++[>++++++++++++++++<-]", Some((1, 256))),
+            (b"+++++++++++++++[>+++++++++++++++++<-]>[-]", None),
+            (b"+>++<[+>-]", Some((2, -1))),
+            (b"-", Some((0, -1))),
+            (&[b'-'; 200][..], Some((0, -127))),
+            (&long[..], None),
+            (b"-+", Some((0, -1))),
+            (b"->+<+", Some((0, -1))),
+            (b"+[--+]", Some((0, -1))),
+            (b"+[--+<+>]", Some((0, -1))),
+            (b"+[>-+]", Some((1, -1))),
+        ];
+
+        for (code, expected) in cases.iter() {
+            let prog = parse_strict(code);
+            let mut state = State::default();
+            let result = eval_state(&prog, &mut empty(), &mut sink(), &mut state, &config, &mut ());
+
+            let found = result.err().map(|err| {
+                let overflow = Overflow::find(&err).unwrap();
+                (overflow.cell, overflow.value)
+            });
+
+            assert_eq!(&found, expected);
+        }
+    }
+
+    // The cells which are not 0 and the pointer, relative to where the
+    // program started.
+    fn relative(state: &State) -> (Vec<(isize, u8)>, isize) {
+        let cells = state.tape.iter().enumerate()
+            .filter(|x| x.1.0 != 0)
+            .map(|(pos, val)| (pos as isize - state.origin, val.0))
+            .collect();
+
+        (cells, state.index - state.origin)
+    }
+
+    #[test]
+    fn eval_walk() {
+        let codes: [&[u8]; 4] = [
+            b"+++>++>+>+++<<<[-<+>>]",
+            b">>>>+>++>+++[->>+<<<]",
+            b"+>+>+[-<<<+>]",
+            b"+>>+>>+>>+[+>>]<<[>+<+++[-<<]]",
+        ];
+
+        for code in codes.iter() {
+            let prog = parse(code);
+            assert!(format!("{:?}", prog).contains("Walk"), "{:?}", prog);
+
+            let mut state = State::default();
+            eval_state(&prog, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut ()).unwrap();
+
+            let mut expected = State::default();
+            run(code, &mut empty(), &mut sink(), &mut expected, &Config::default(), &mut ()).unwrap();
+
+            assert_eq!(relative(&state), relative(&expected));
+        }
+    }
+
     #[test]
     fn eval_cat() {
         let code = b",[+++.,]";
         let prog = parse(code);
-        let mut tape = VecDeque::new();
         let mut input: &[u8] = b"abc";
         let mut output = Vec::new();
-        eval(&prog, &mut input, &mut output, &mut tape, 0);
+        eval(&prog, &mut input, &mut output);
         assert_eq!(output, b"def");
     }
 }
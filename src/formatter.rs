@@ -0,0 +1,282 @@
+use crate::parser::*;
+
+const INDENT: usize = 4;
+
+fn is_command(byte: u8) -> bool {
+    b"+-<>[].,".contains(&byte)
+}
+
+// Runs are split where the code switches between changing cells, moving the
+// pointer and doing io, like in `> ++++ < -`.
+fn kind(byte: u8) -> u8 {
+    match byte {
+        b'+' | b'-' => 0,
+        b'<' | b'>' => 1,
+        _ => 2,
+    }
+}
+
+fn words(commands: &[u8]) -> Vec<Vec<u8>> {
+    let mut words: Vec<Vec<u8>> = Vec::new();
+
+    for &byte in commands.iter() {
+        match words.last_mut() {
+            Some(word) if kind(word[0]) == kind(byte) => word.push(byte),
+            _ => words.push(vec![byte]),
+        }
+    }
+
+    words
+}
+
+struct Layout {
+    width: usize,
+    depth: usize,
+    lines: Vec<Vec<u8>>,
+    // whether more code may be put on the last line
+    open: bool,
+}
+
+impl Layout {
+    fn push_line(&mut self, text: &[u8], open: bool) {
+        let mut line = vec![b' '; self.depth * INDENT];
+        line.extend_from_slice(text);
+
+        self.lines.push(line);
+        self.open = open;
+    }
+
+    fn fits(&self, text: &[u8]) -> bool {
+        self.lines.last().is_some_and(|x| x.len() + 1 + text.len() <= self.width)
+    }
+
+    fn code(&mut self, text: &[u8]) {
+        if self.open && self.fits(text) {
+            let line = self.lines.last_mut().unwrap();
+            line.push(b' ');
+            line.extend_from_slice(text);
+            return;
+        }
+
+        // only runs can be longer than a line, and those can be split up
+        let room = self.width.saturating_sub(self.depth * INDENT).max(1);
+
+        for chunk in text.chunks(room) {
+            self.push_line(chunk, true);
+        }
+    }
+
+    fn comment(&mut self, words: &[&[u8]], trailing: bool) {
+        let mut words = words.iter();
+
+        if !trailing {
+            self.open = false;
+        } else {
+            while let Some(word) = words.as_slice().first() {
+                if !self.fits(word) {
+                    break;
+                }
+
+                let line = self.lines.last_mut().unwrap();
+                line.push(b' ');
+                line.extend_from_slice(word);
+                words.next();
+            }
+        }
+
+        for word in words {
+            if self.open && self.fits(word) {
+                let line = self.lines.last_mut().unwrap();
+                line.push(b' ');
+                line.extend_from_slice(word);
+            } else {
+                self.push_line(word, true);
+            }
+        }
+
+        self.open = false;
+    }
+
+    fn blank(&mut self) {
+        if self.lines.last().is_some_and(|x| !x.is_empty()) {
+            self.lines.push(Vec::new());
+            self.open = false;
+        }
+    }
+}
+
+// Everything between two commands is kept as comments, one for each line
+// it spans. Empty lines in between are kept as a single empty line.
+fn gap(layout: &mut Layout, text: &[u8], after_code: bool) {
+    let lines: Vec<&[u8]> = text.split(|&x| x == b'\n').collect();
+
+    for (index, line) in lines.iter().enumerate() {
+        let words: Vec<&[u8]> = line
+            .split(|x| x.is_ascii_whitespace())
+            .filter(|x| !x.is_empty())
+            .collect();
+
+        if !words.is_empty() {
+            layout.comment(&words, index == 0 && after_code);
+        } else if index > 0 && index + 1 < lines.len() {
+            layout.blank();
+        }
+    }
+}
+
+fn has_comment(text: &[u8]) -> bool {
+    text.iter().any(|x| !x.is_ascii_whitespace())
+        || text.iter().filter(|&&x| x == b'\n').count() > 1
+}
+
+fn inline_loop(body: &[u8]) -> Option<Vec<u8>> {
+    if body.iter().any(|&x| x == b'[' || !is_command(x) && !x.is_ascii_whitespace()) {
+        return None;
+    }
+
+    let commands: Vec<u8> = body.iter().copied().filter(|&x| is_command(x)).collect();
+    let text = words(&commands).join(&b' ');
+
+    Some([&b"["[..], &text, b"]"].concat())
+}
+
+fn format_range(layout: &mut Layout, code: &[u8], jumps: &[usize], start: usize, end: usize) {
+    let mut run = Vec::new();
+    let mut pos = start;
+
+    while pos < end {
+        let byte = code[pos];
+
+        if !is_command(byte) {
+            let next = (pos..end).find(|&x| is_command(code[x])).unwrap_or(end);
+            let text = &code[pos..next];
+
+            if has_comment(text) {
+                for word in words(&run) {
+                    layout.code(&word);
+                }
+
+                run.clear();
+                gap(layout, text, pos > 0);
+            }
+
+            pos = next;
+            continue;
+        }
+
+        if byte != b'[' {
+            run.push(byte);
+            pos += 1;
+            continue;
+        }
+
+        for word in words(&run) {
+            layout.code(&word);
+        }
+
+        run.clear();
+
+        let close = jumps[pos];
+        let room = layout.width.saturating_sub(layout.depth * INDENT);
+
+        match inline_loop(&code[pos + 1..close]) {
+            Some(text) if text.len() <= room => layout.code(&text),
+
+            _ => {
+                layout.push_line(b"[", false);
+                layout.depth += 1;
+                format_range(layout, code, jumps, pos + 1, close);
+                layout.depth -= 1;
+                layout.push_line(b"]", false);
+            },
+        }
+
+        pos = close + 1;
+    }
+
+    for word in words(&run) {
+        layout.code(&word);
+    }
+}
+
+// Lays out the code with one level of indentation per loop and wraps lines
+// at the given width. Only whitespace is changed, so the result runs
+// exactly like the original. Returns the position of an unmatched bracket
+// if there is one.
+pub fn format_source(code: &[u8], width: usize) -> Result<Vec<u8>, usize> {
+    let jumps = match_brackets(code)?;
+
+    let mut layout = Layout { width, depth: 0, lines: Vec::new(), open: false };
+    format_range(&mut layout, code, &jumps, 0, code.len());
+
+    while layout.lines.last().is_some_and(Vec::is_empty) {
+        layout.lines.pop();
+    }
+
+    let mut output = Vec::new();
+
+    for line in layout.lines.iter() {
+        output.extend_from_slice(line);
+        output.push(b'\n');
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::formatter::*;
+
+    #[test]
+    fn format_nested_loops() {
+        let code = b"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.";
+        let expected = concat!(
+            "++++++++\n",
+            "[\n",
+            "    > ++++ [> ++ > +++ > +++ > + <<<< -] > + > + > - >> + [<] < -\n",
+            "]\n",
+            ">> . > --- .\n",
+        );
+
+        assert_eq!(String::from_utf8(format_source(code, 80).unwrap()).unwrap(), expected);
+        assert_eq!(format_source(b"+[", 80), Err(1));
+    }
+
+    #[test]
+    fn format_keeps_comments() {
+        let code = b"set up # the counter\n+++++  [ loop\n>+ copy\n\n\n<-] done!\n";
+        let expected = concat!(
+            "set up # the counter\n",
+            "+++++\n",
+            "[ loop\n",
+            "    > + copy\n",
+            "\n",
+            "    < -\n",
+            "] done!\n",
+        );
+
+        let formatted = format_source(code, 80).unwrap();
+        assert_eq!(String::from_utf8(formatted.clone()).unwrap(), expected);
+
+        let comments = |x: &[u8]| -> Vec<u8> {
+            x.iter().copied().filter(|x| !x.is_ascii_whitespace() && !is_command(*x)).collect()
+        };
+
+        assert_eq!(comments(&formatted), comments(code));
+        assert_eq!(parse(&formatted), parse(code));
+    }
+
+    #[test]
+    fn format_is_stable() {
+        let code = b"a long comment which needs to be wrapped +++ [->>+<<] >>> \
+            ---------------------------------------- [ c [<] ] x\n.";
+
+        for width in [10, 20, 40].iter() {
+            let once = format_source(code, *width).unwrap();
+            let twice = format_source(&once, *width).unwrap();
+
+            assert_eq!(twice, once);
+            assert_eq!(parse(&once), parse(code));
+        }
+    }
+}
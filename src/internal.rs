@@ -11,12 +11,76 @@ pub enum IR {
     Mul(isize, Wrapping<u8>),
     Move(isize),
     Store(isize),
+    Count(isize, Wrapping<u8>),
     Loop(Vec<IR>),
-    FixedLoop(Vec<IR>,isize,isize),
+    FixedLoop(Vec<IR>, isize, isize, isize),
+    If(Vec<IR>, isize, isize, isize),
     Scan(Wrapping<u8>, isize),
     Fill(isize, Wrapping<u8>, isize),
+    Walk(Vec<(isize, Wrapping<u8>)>, isize),
     Input(isize),
     Output(isize),
+    Print(Vec<u8>),
+}
+
+// The inverse of an odd number modulo 256, where each round of Newton's
+// method doubles the number of correct low bits.
+pub fn inverse(odd: Wrapping<u8>) -> Wrapping<u8> {
+    let mut inv = odd;
+
+    for _ in 0..3 {
+        inv *= Wrapping(2) - odd * inv;
+    }
+
+    inv
+}
+
+// How often a loop adding step to its counter runs until the counter is 0,
+// if it ever gets there. With an even step, only some counters are reached.
+pub fn trip_count(cell: Wrapping<u8>, step: Wrapping<u8>) -> Option<Wrapping<u8>> {
+    let shift = step.0.trailing_zeros();
+
+    if step.0 == 0 || cell.0.trailing_zeros() < shift {
+        return if cell.0 == 0 { Some(Wrapping(0)) } else { None };
+    }
+
+    let count = Wrapping(cell.0 >> shift) * inverse(Wrapping((-step).0 >> shift));
+    Some(Wrapping(count.0 & (0xff >> shift)))
+}
+
+// Shifts every cell a piece of code uses by the same amount, which lets a
+// loop that does not move the pointer run at an offset instead of after a
+// move. Loops which do move it always start at the pointer, so they cannot
+// be part of the code.
+pub fn rebase(prog: &mut [IR], by: isize) {
+    for inst in prog.iter_mut() {
+        match inst {
+            IR::Touch(high, low) => {
+                *high += by;
+                *low += by;
+            },
+
+            IR::Set(off, _) | IR::Add(off, _) | IR::Mul(off, _) | IR::Store(off) |
+            IR::Count(off, _) | IR::Input(off) | IR::Output(off) => *off += by,
+
+            IR::FixedLoop(sub, off, high, low) | IR::If(sub, off, high, low) => {
+                rebase(sub, by);
+                *off += by;
+                *high += by;
+                *low += by;
+            },
+
+            IR::Start | IR::Move(_) | IR::Print(_) |
+            IR::Loop(_) | IR::Scan(_, _) | IR::Fill(_, _, _) | IR::Walk(_, _) => (),
+        }
+    }
+}
+
+// Adds are folded from runs of `+` and `-`, so their direction is only known
+// when the run was shorter than half the range of a cell.
+#[inline]
+pub fn signed(val: Wrapping<u8>) -> i32 {
+    val.0 as i8 as i32
 }
 
 impl fmt::Display for IR {
@@ -29,22 +93,35 @@ impl fmt::Display for IR {
             IR::Mul(off, val) => write!(f, "mul {:+} {}", off, val),
             IR::Move(off) => write!(f, "mov {:+}", off),
             IR::Store(off) => write!(f, "store {:+}", off),
+            IR::Count(off, step) => write!(f, "count {:+} {}", off, step),
             IR::Loop(_) => write!(f, "loop"),
-            IR::FixedLoop(_, high, low) => write!(f, "loop {:+} {:+} (fix)", high, low),
+            IR::FixedLoop(_, off, high, low) => write!(f, "loop {:+} {:+} {:+} (fix)", off, high, low),
+            IR::If(_, off, high, low) => write!(f, "if {:+} {:+} {:+}", off, high, low),
             IR::Scan(val, step) => write!(f, "scan {} {:+}", val, step),
             IR::Fill(off, val, step) => write!(f, "fill {:+} {} {:+}", off, val, step),
+
+            IR::Walk(adds, step) => {
+                write!(f, "walk {:+}", step)?;
+
+                for (off, val) in adds.iter() {
+                    write!(f, " {:+}:{}", off, val)?;
+                }
+
+                Ok(())
+            },
             IR::Input(off) => write!(f, "in {:+}", off),
             IR::Output(off) => write!(f, "out {:+}", off),
+            IR::Print(bytes) => write!(f, "print \"{}\"", bytes.escape_ascii()),
         }
     }
 }
 
-fn write_code(prog: &[IR], ind: u32, lines: &mut Vec<String>) {
+pub fn write_code(prog: &[IR], ind: u32, lines: &mut Vec<String>) {
     for inst in prog.iter() {
         let padding = "| ".repeat(ind as _);
 
         match inst {
-            IR::Loop(sub) | IR::FixedLoop(sub, _, _) => {
+            IR::Loop(sub) | IR::FixedLoop(sub, _, _, _) | IR::If(sub, _, _, _) => {
                 let line = format!("{}{}", padding, inst);
                 lines.push(line);
 
@@ -52,7 +129,7 @@ fn write_code(prog: &[IR], ind: u32, lines: &mut Vec<String>) {
             },
 
             IR::Fill(_, _, _) => {
-                let line = format!("{}{}", padding.replace('|', &"#"), inst);
+                let line = format!("{}{}", padding.replace('|', "#"), inst);
                 lines.push(line);
             }
 
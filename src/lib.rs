@@ -0,0 +1,21 @@
+#![warn(clippy::all)]
+
+pub mod internal;
+pub mod parser;
+pub mod evaluator;
+pub mod profiler;
+pub mod naive;
+pub mod coverage;
+pub mod trace;
+pub mod snapshot;
+pub mod repl;
+pub mod terminal;
+pub mod streams;
+pub mod bignum;
+pub mod portability;
+pub mod compile;
+pub mod debugger;
+pub mod formatter;
+pub mod lint;
+pub mod prefix;
+pub mod bulk;
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use crate::parser::*;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Warning {
+    pub pos: usize,
+    pub message: String,
+}
+
+// What is known about a cell at some point of the program. `Lowered` is a
+// cell of unknown value which has been decremented since, so it may well be
+// negative if cells do not wrap around.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Value {
+    Known(i64),
+    NonNeg,
+    Unknown,
+    Lowered,
+}
+
+fn is_command(byte: u8) -> bool {
+    b"+-<>[].,".contains(&byte)
+}
+
+// Characters which are easily typed instead of a command, together with the
+// commands they may have been meant as.
+const LOOKALIKES: &[(u8, &str)] = &[
+    (b'=', "`+` or `-`"),
+    (b'_', "`-`"),
+    (b'~', "`-`"),
+    (b'{', "`[`"),
+    (b'(', "`[`"),
+    (b'}', "`]`"),
+    (b')', "`]`"),
+    (b';', "`,`"),
+    (b':', "`.`"),
+];
+
+struct Linter<'a> {
+    code: &'a [u8],
+    jumps: Vec<usize>,
+    wrapping: bool,
+    cells: HashMap<isize, Value>,
+    rest: Value,
+    ptr: isize,
+    // false in the body of a loop which never runs
+    live: bool,
+    // set once an infinite loop has been found, since nothing after it runs
+    dead: bool,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Linter<'a> {
+    fn warn(&mut self, pos: usize, message: String) {
+        self.warnings.push(Warning { pos, message });
+    }
+
+    fn get(&self, off: isize) -> Value {
+        *self.cells.get(&(self.ptr + off)).unwrap_or(&self.rest)
+    }
+
+    fn set(&mut self, off: isize, value: Value) {
+        let value = match value {
+            Value::Known(val) if self.wrapping => Value::Known(val.rem_euclid(256)),
+            Value::Lowered if self.wrapping => Value::NonNeg,
+            value => value,
+        };
+
+        self.cells.insert(self.ptr + off, value);
+    }
+
+    fn add(&mut self, delta: i64) {
+        let value = match self.get(0) {
+            Value::Known(val) => Value::Known(val + delta),
+            Value::NonNeg if delta > 0 => Value::NonNeg,
+            Value::Unknown if delta > 0 => Value::Unknown,
+            _ => Value::Lowered,
+        };
+
+        self.set(0, value);
+    }
+
+    // Returns the net pointer movement and the changes to each cell of a
+    // body which only consists of `+-<>`.
+    fn simple_body(&self, open: usize) -> Option<(isize, HashMap<isize, i64>)> {
+        let mut ptr = 0;
+        let mut changes = HashMap::new();
+
+        for &byte in self.code[open + 1..self.jumps[open]].iter() {
+            match byte {
+                b'+' => *changes.entry(ptr).or_default() += 1,
+                b'-' => *changes.entry(ptr).or_default() -= 1,
+                b'>' => ptr += 1,
+                b'<' => ptr -= 1,
+                _ if is_command(byte) => return None,
+                _ => (),
+            }
+        }
+
+        Some((ptr, changes))
+    }
+
+    fn enter_loop(&mut self, open: usize) {
+        let close = self.jumps[open];
+        let body: Vec<u8> = self.code[open + 1..close].iter().copied().filter(|&x| is_command(x)).collect();
+        let first = !self.code[..open].iter().any(|&x| is_command(x));
+        let value = self.get(0);
+        let live = self.live && !self.dead;
+
+        let never_runs = live && value == Value::Known(0);
+        let never_ends = live && matches!(value, Value::Known(val) if val != 0)
+            && matches!(self.simple_body(open), Some((0, ref changes)) if changes.get(&0).copied().unwrap_or(0) == 0);
+
+        // a comment loop at the very start is a common idiom, and so is
+        // clearing cells which are already clear just to be sure
+        if never_runs && !first && body != b"-" && body != b"+" {
+            self.warn(open, "loop never runs, since the cell is always 0 here".to_string());
+        }
+
+        if never_ends {
+            self.warn(open, "loop never ends, since the cell is never changed".to_string());
+        } else if body.is_empty() && !never_runs && !(first && value == Value::Known(0)) {
+            self.warn(open, "empty loop never ends if the cell is not 0".to_string());
+        }
+
+        if live && !self.wrapping && body == b"-" {
+            match value {
+                Value::Known(val) if val < 0 => self.warn(
+                    open,
+                    format!("`[-]` never ends, since the cell is always {} here", val)
+                ),
+                Value::Lowered => self.warn(
+                    open,
+                    "`[-]` never ends if the cell has been lowered below 0".to_string()
+                ),
+                _ => (),
+            }
+        }
+
+        let saved = (self.cells.clone(), self.rest, self.ptr);
+        let outer = self.live;
+
+        self.cells.clear();
+        self.rest = Value::Unknown;
+        self.live = live && !never_runs;
+        self.walk(open + 1, close);
+        self.live = outer;
+
+        if never_runs {
+            self.cells = saved.0;
+            self.rest = saved.1;
+            self.ptr = saved.2;
+            return;
+        }
+
+        match self.simple_body(open) {
+            // only the cells changed in a balanced loop are unknown after it
+            Some((0, changes)) => {
+                self.cells = saved.0;
+                self.rest = saved.1;
+                self.ptr = saved.2;
+
+                for (off, _) in changes.into_iter().filter(|x| x.1 != 0) {
+                    self.set(off, Value::Unknown);
+                }
+            },
+
+            _ => {
+                self.cells.clear();
+                self.rest = Value::Unknown;
+            },
+        }
+
+        self.set(0, Value::Known(0));
+
+        if never_ends && !self.dead {
+            self.dead = true;
+
+            if let Some(next) = (close + 1..self.code.len()).find(|&x| is_command(self.code[x])) {
+                self.warn(next, "code after an infinite loop is never reached".to_string());
+            }
+        }
+    }
+
+    fn walk(&mut self, start: usize, end: usize) {
+        let mut pos = start;
+
+        while pos < end {
+            match self.code[pos] {
+                b'+' => self.add(1),
+                b'-' => self.add(-1),
+                b'>' => self.ptr += 1,
+                b'<' => self.ptr -= 1,
+                b',' => self.set(0, Value::NonNeg),
+
+                b'[' => {
+                    self.enter_loop(pos);
+                    pos = self.jumps[pos];
+                },
+
+                _ => (),
+            }
+
+            pos += 1;
+        }
+    }
+
+    fn cancellations(&mut self) {
+        let mut prev: Option<(usize, u8)> = None;
+
+        for (pos, &byte) in self.code.iter().enumerate() {
+            if byte.is_ascii_whitespace() {
+                continue;
+            }
+
+            if let Some((last, other)) = prev {
+                if [other, byte] == *b"+-" || [other, byte] == *b"-+"
+                    || [other, byte] == *b"<>" || [other, byte] == *b"><" {
+                    self.warn(last, format!("`{}{}` cancels out", other as char, byte as char));
+                    prev = None;
+                    continue;
+                }
+            }
+
+            prev = if b"+-<>".contains(&byte) { Some((pos, byte)) } else { None };
+        }
+    }
+
+    fn typos(&mut self) {
+        for pos in 1..self.code.len().saturating_sub(1) {
+            let byte = self.code[pos];
+
+            if !is_command(self.code[pos - 1]) || !is_command(self.code[pos + 1]) {
+                continue;
+            }
+
+            if let Some((_, meant)) = LOOKALIKES.iter().find(|x| x.0 == byte) {
+                self.warn(pos, format!("`{}` between commands looks like a typo of {}", byte as char, meant));
+            }
+        }
+    }
+}
+
+// Looks for code which most likely does not do what it was meant to do.
+// Returns the position of an unmatched bracket if there is one.
+pub fn lint(code: &[u8], wrapping: bool) -> Result<Vec<Warning>, usize> {
+    let mut linter = Linter {
+        code,
+        jumps: match_brackets(code)?,
+        wrapping,
+        cells: HashMap::new(),
+        rest: Value::Known(0),
+        ptr: 0,
+        live: true,
+        dead: false,
+        warnings: Vec::new(),
+    };
+
+    linter.cancellations();
+    linter.typos();
+    linter.walk(0, code.len());
+
+    let mut warnings = linter.warnings;
+    warnings.sort_by_key(|x| x.pos);
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lint::*;
+
+    fn positions(code: &[u8], wrapping: bool) -> Vec<(usize, String)> {
+        lint(code, wrapping).unwrap().into_iter().map(|x| (x.pos, x.message)).collect()
+    }
+
+    #[test]
+    fn lint_cancellations_and_typos() {
+        let warnings = positions(b"++ -> <+=+ a;b.{.", true);
+
+        assert_eq!(warnings, vec![
+            (1, "`+-` cancels out".to_string()),
+            (4, "`><` cancels out".to_string()),
+            (8, "`=` between commands looks like a typo of `+` or `-`".to_string()),
+            (15, "`{` between commands looks like a typo of `[`".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn lint_dead_loops() {
+        let code = b"[comment]+[-]>[-]<[>.<]++[]>.";
+        let warnings: Vec<usize> = positions(code, true).into_iter().map(|x| x.0).collect();
+        assert_eq!(warnings, vec![18, 25, 27]);
+
+        let messages = positions(b",[>+<-][<]", true);
+        assert_eq!(messages, vec![(7, "loop never runs, since the cell is always 0 here".to_string())]);
+
+        let messages = positions(b",[[]]", true);
+        assert_eq!(messages, vec![(2, "empty loop never ends if the cell is not 0".to_string())]);
+    }
+
+    #[test]
+    fn lint_negative_clear() {
+        let code = b"--[-],----------[-]+[-]";
+        let warnings = positions(code, false);
+
+        assert_eq!(warnings, vec![
+            (2, "`[-]` never ends, since the cell is always -2 here".to_string()),
+            (16, "`[-]` never ends if the cell has been lowered below 0".to_string()),
+        ]);
+
+        assert!(positions(code, true).is_empty());
+    }
+}
@@ -1,54 +1,101 @@
 #![warn(clippy::all)]
 
 use std::env::args;
-use std::io::{Read, stdin, stdout};
-use std::fs::File;
+use std::io::{Read, Write, Error, ErrorKind, BufReader, BufWriter, copy, sink, stdin, stdout, stderr};
+use std::fs::{File, read, read_to_string, write};
+use std::path::Path;
 use std::num::Wrapping;
 use std::collections::VecDeque;
-use std::iter::repeat;
-use getopts::Options;
+use std::iter::repeat_n;
+use std::process::exit;
+use std::time::{Duration, Instant};
+use getopts::{Options, Matches};
 
-mod internal;
-mod parser;
-mod evaluator;
+use rbf::internal::*;
+use rbf::parser::*;
+use rbf::evaluator::*;
+use rbf::profiler::*;
+use rbf::naive::*;
+use rbf::coverage::*;
+use rbf::trace::*;
+use rbf::snapshot::*;
+use rbf::repl::*;
+use rbf::terminal::*;
+use rbf::streams::*;
+use rbf::bignum::*;
+use rbf::portability::*;
+use rbf::compile::*;
+use rbf::debugger::*;
+use rbf::formatter::*;
+use rbf::lint::*;
+use rbf::prefix::*;
 
-use internal::*;
-use parser::*;
-use evaluator::*;
+const COMMANDS: &str = "\
+Commands:
+    run         run a program, which is also done without a command
+    show        print the generated code
+    check       check a program for problems
+    lint        warn about code which likely does not do what it should
+    fmt         format the source code of programs
+    compile     translate a program into another language
+    test        run programs and compare their output with the expected one
+    debug       step through a program on the source level
+    bench       measure how long a program takes to run
+    repl        evaluate code line by line";
 
-fn print_usage(program: &str, opts: &Options) {
-    println!("Usage: {} FILE [options]\n", program);
-    print!("A simple optimizing Brainfuck interpreter written in Rust.");
+fn print_help(program: &str) {
+    println!("Usage: {} COMMAND [options]", program);
+    println!("       {} FILE [options]\n", program);
+    println!("A simple optimizing Brainfuck interpreter written in Rust.\n");
+    println!("{}\n", COMMANDS);
+    println!("Run '{} COMMAND --help' to see the options of a command.", program);
+}
+
+fn print_usage(program: &str, usage: &str, opts: &Options) {
+    print!("Usage: {} {}", program, usage);
     println!("{}", opts.usage(""));
 }
 
-fn main() {
-    let args: Vec<String> = args().collect();
-    let program = args.get(0).unwrap();
+fn parse_args(opts: &Options, args: &[String]) -> Matches {
+    match opts.parse(args) {
+        Ok(matches) => matches,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            exit(2);
+        },
+    }
+}
 
-    let mut opts = Options::new();
+fn code_options(opts: &mut Options) {
     opts.optflag("h", "help", "print this help message");
-    opts.optopt("c", "cmd", "run code from command", "CODE");
-    opts.optopt("i", "input", "use string as input", "TEXT");
-    opts.optflag("f", "force", "allow invalid code to run");
-    opts.optflag("s", "static", "only print the generated code");
+    opts.optopt("c", "cmd", "use code from the command line instead of a file", "CODE");
+    opts.optflag("b", "bang", "use everything after the first '!' as input");
+}
 
-    let matches = opts.parse(&args[1..]).expect("failed to parse args");
+fn input_options(opts: &mut Options) {
+    opts.optmulti("i", "input", "use string as input", "TEXT");
+    opts.optmulti("", "input-file", "use the contents of a file as input", "FILE");
+}
 
+// Returns the code, its inline input and whether it was read from stdin, or
+// nothing if the usage should be printed instead.
+fn read_code(matches: &Matches) -> Option<(Vec<u8>, Vec<u8>, bool)> {
     if matches.opt_present("h") {
-        print_usage(program, &opts);
-        return;
+        return None;
     }
 
     let mut code: Vec<u8> = Vec::new();
+    let mut code_from_stdin = false;
 
     if let Some(command) = matches.opt_str("c") {
         code = command.as_bytes().to_vec();
-    } else if let Some(file_name) = matches.free.get(0) {
+    } else if let Some(file_name) = matches.free.first() {
         if file_name == "-" {
             stdin()
                 .read_to_end(&mut code)
                 .expect("failed to read stdin");
+
+            code_from_stdin = true;
         } else {
             File::open(file_name)
                 .expect("failed to open file")
@@ -56,27 +103,748 @@ fn main() {
                 .expect("failed to read file");
         }
     } else {
-        print_usage(&program, &opts);
-        return;
-    };
+        return None;
+    }
 
-    let prog = parse(&code);
+    let mut inline = Vec::new();
+
+    if matches.opt_defined("b") && matches.opt_present("b") {
+        let (head, tail) = split_input(&code);
+        inline = tail.to_vec();
+        code.truncate(head.len());
+    }
+
+    Some((code, inline, code_from_stdin))
+}
 
-    if matches.opt_present("s") {
-        show_code(&prog, 120);
-        return;
+fn input_sources(matches: &Matches) -> Vec<Source> {
+    let mut sources = Vec::new();
+
+    for (pos, text) in matches.opt_strs_pos("i") {
+        let text = decode_escapes(&text).expect("invalid input string");
+        sources.push((pos, Source::Text(text)));
+    }
+
+    for (pos, path) in matches.opt_strs_pos("input-file") {
+        let source = if path == "-" { Source::Stdin } else { Source::File(path) };
+        sources.push((pos, source));
+    }
+
+    sources.sort_by_key(|x| x.0);
+    sources.into_iter().map(|x| x.1).collect()
+}
+
+fn read_input(inline: Vec<u8>, sources: Vec<Source>) -> Vec<u8> {
+    let mut sources = sources;
+    sources.insert(0, Source::Text(inline));
+
+    let mut data = Vec::new();
+    open_inputs(sources)
+        .expect("failed to open input")
+        .read_to_end(&mut data)
+        .expect("failed to read input");
+
+    data
+}
+
+fn fresh_state() -> State {
+    let mut tape = VecDeque::with_capacity(0x2000);
+    tape.extend(repeat_n(Wrapping(0u8), 0x1000));
+    State::new(tape, 0x400)
+}
+
+fn unwrap_run(code: &[u8], result: Result<bool, Error>) -> bool {
+    let err = match result {
+        Ok(done) => return done,
+        Err(err) => err,
+    };
+
+    match Overflow::find(&err) {
+        Some(overflow) => match overflow.pos {
+            Some(pos) => {
+                let (line, column) = line_column(code, pos);
+                panic!("{} at line {}, column {}", overflow, line, column);
+            },
+            None => panic!("{}", overflow),
+        },
+        None => panic!("failed to run program: {:?}", err),
     }
+}
+
+fn run_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    code_options(&mut opts);
+    input_options(&mut opts);
+    opts.optopt("o", "output-file", "write output to a file", "FILE");
+    opts.optopt("", "input-mode", "read input as char, utf8, decimal or hex numbers", "MODE");
+    opts.optopt("", "output-mode", "write output as char, utf8, decimal or hex numbers", "MODE");
+    opts.optopt("", "invalid-input", "error, replace or skip on invalid utf8 input", "POLICY");
+    opts.optflag("", "strict-cells", "stop when a cell would go below 0 or above 255");
+    opts.optflag("", "bignum", "use cells of unlimited size without wrapping");
+    opts.optflag("f", "force", "allow invalid code to run");
+    opts.optflag("p", "profile", "print an execution profile to stderr");
+    opts.optopt("", "coverage", "write an annotated copy of the source", "FILE");
+    opts.optopt("", "trace", "write an execution trace as json lines", "FILE");
+    opts.optopt("", "trace-filter", "only trace io, loops or a source range", "FILTER");
+    opts.optflag("", "trace-source", "trace source commands instead of instructions");
+    opts.optflag("", "raw-input", "read keys from the terminal as they are pressed");
+    opts.optflag("", "raw-enter", "pass enter as 13 instead of 10 in raw mode");
+    opts.optopt("", "flush", "flush output at exit, newline, input or never", "WHEN");
+    opts.optopt("", "max-steps", "pause after this many loop iterations", "N");
+    opts.optopt("", "save-state", "save the machine state when the program stops", "FILE");
+    opts.optopt("", "load-state", "resume from a saved machine state", "FILE");
+    opts.optflag("", "dump-tape", "print the final tape to stderr");
+    opts.optopt("", "init-tape", "start with the cell values from a file", "FILE");
+    opts.optflag("", "no-prefix", "do not run the start of the program ahead of time");
+
+    let matches = parse_args(&opts, args);
+    let usage = "run FILE [options]\n\nRun a program, reading its input from stdin unless told otherwise.";
+
+    let (code, inline, code_from_stdin) = match read_code(&matches) {
+        Some(code) => code,
+        None => return print_usage(program, usage, &opts),
+    };
+
+    let strict = matches.opt_present("strict-cells");
+    let mut prog = if strict { parse_strict(&code) } else { parse(&code) };
 
     if !matches.opt_present("f") && !check_valid(&code) {
         panic!("invalid code");
     }
 
-    let mut tape = VecDeque::with_capacity(0x2000);
-    tape.extend(repeat(Wrapping(0u8)).take(0x1000));
+    let mut state = fresh_state();
+
+    if let Some(path) = matches.opt_str("load-state") {
+        let mut file = File::open(path).expect("failed to open file");
+        state = load(&mut file, &prog).expect("failed to load state");
+    }
+
+    if let Some(path) = matches.opt_str("init-tape") {
+        let text = read_to_string(path).expect("failed to read file");
+        init_tape(&mut state, &text).expect("failed to initialize tape");
+    }
+
+    let mut config = Config { strict, ..Config::default() };
+
+    if let Some(text) = matches.opt_str("max-steps") {
+        config.limit = text.parse().expect("invalid step count");
+    }
+
+    if let Some(text) = matches.opt_str("flush") {
+        config.flush = Flush::parse(&text).expect("invalid flush policy");
+    }
+
+    let mut sources = input_sources(&matches);
+
+    if sources.is_empty() && !code_from_stdin {
+        sources.push(Source::Stdin);
+    }
+
+    let reads_stdin = sources.contains(&Source::Stdin);
+    sources.insert(0, Source::Text(inline));
+
+    let mut input = open_inputs(sources).expect("failed to open input");
+
+    let mut output: Box<dyn Write> = match matches.opt_str("o") {
+        Some(path) => Box::new(File::create(path).expect("failed to create file")),
+        None => Box::new(stdout().lock()),
+    };
+
+    let mut format = Format::default();
+
+    if let Some(text) = matches.opt_str("input-mode") {
+        format.input = Mode::parse(&text).expect("invalid input mode");
+    }
+
+    if let Some(text) = matches.opt_str("output-mode") {
+        format.output = Mode::parse(&text).expect("invalid output mode");
+    }
+
+    if let Some(text) = matches.opt_str("invalid-input") {
+        format.invalid = Invalid::parse(&text).expect("invalid policy for invalid input");
+    }
+
+    let bignum = matches.opt_present("bignum");
+
+    if bignum {
+        let unsupported = ["profile", "coverage", "trace", "save-state", "load-state", "init-tape"];
+
+        if let Some(name) = unsupported.iter().find(|&&x| matches.opt_present(x)) {
+            panic!("--{} cannot be used together with --bignum", name);
+        }
+    } else {
+        if format.input != Mode::Char {
+            input = Box::new(CellReader::new(BufReader::new(input), format.input, format.invalid));
+        }
 
-    if let Some(input) = matches.opt_str("i") {
-        eval(&prog, &mut input.as_bytes(), &mut stdout().lock(), &mut tape, 0x400);
+        if format.output != Mode::Char {
+            output = Box::new(CellWriter::new(output, format.output));
+        }
+    }
+
+    copy(&mut (&mut input).take(state.consumed), &mut sink())
+        .expect("failed to skip consumed input");
+
+    let raw_mode = if matches.opt_present("raw-input") && reads_stdin {
+        RawMode::enable(!matches.opt_present("raw-enter")).expect("failed to enable raw input")
+    } else {
+        None
+    };
+
+    let resumed = !state.position.is_empty();
+    let source_level = matches.opt_present("coverage")
+        || matches.opt_present("trace") && matches.opt_present("trace-source");
+
+    if resumed && source_level {
+        panic!("cannot resume a saved state on the source level");
+    }
+
+    // the prefix is only run ahead on a fresh tape and when no instruction
+    // has to be seen by an observer or kept for a saved state
+    let keep = ["p", "coverage", "trace", "save-state", "load-state", "init-tape", "no-prefix"];
+
+    if !bignum && !keep.iter().any(|&x| matches.opt_present(x)) {
+        state.steps = evaluate_prefix(&mut prog, &config);
+    }
+
+    let mut big_state = BigState::default();
+
+    let done = if bignum {
+        let prog = parse_big(&code).expect("invalid code");
+        eval_big(&prog, BufReader::new(&mut input), &mut output, &mut big_state, &config, &format)
+            .expect("failed to run program")
+    } else if matches.opt_present("p") {
+        let mut profile = Profile::default();
+        let result = eval_state(&prog, &mut input, &mut output, &mut state, &config, &mut profile);
+        let done = unwrap_run(&code, result);
+
+        output.flush().expect("failed to flush output");
+        profile.report(&code, &prog, 10);
+        done
+    } else if let Some(path) = matches.opt_str("coverage") {
+        let mut coverage = Coverage::new(&code);
+        let result = run(&code, &mut input, &mut output, &mut state, &config, &mut coverage);
+        let done = unwrap_run(&code, result);
+
+        output.flush().expect("failed to flush output");
+
+        let mut file = File::create(path).expect("failed to create file");
+        coverage.write_annotated(&mut file).expect("failed to write file");
+        coverage.summary();
+        done
+    } else if let Some(path) = matches.opt_str("trace") {
+        let filter = match matches.opt_str("trace-filter") {
+            Some(text) => Filter::parse(&text).expect("invalid trace filter"),
+            None => Filter::All,
+        };
+
+        let file = BufWriter::new(File::create(path).expect("failed to create file"));
+
+        if matches.opt_present("trace-source") || matches!(filter, Filter::Range(_, _)) {
+            let mut tracer = SourceTracer::new(file, filter, &code);
+            let result = run(&code, &mut input, &mut output, &mut state, &config, &mut tracer);
+            let done = unwrap_run(&code, result);
+            tracer.finish().expect("failed to write trace");
+            done
+        } else {
+            let mut tracer = Tracer::new(file, filter);
+            let result = eval_state(&prog, &mut input, &mut output, &mut state, &config, &mut tracer);
+            let done = unwrap_run(&code, result);
+            tracer.finish().expect("failed to write trace");
+            done
+        }
+    } else if strict && !resumed {
+        let initial = state.clone();
+        let mut tee = Tee::new(&mut input);
+        let mut result = eval_state(&prog, &mut tee, &mut output, &mut state, &config, &mut ());
+
+        if let Some(located) = result.as_ref().err()
+            .and_then(Overflow::find)
+            .and_then(|_| locate_overflow(&code, &tee.copy, initial)) {
+            result = Err(Error::new(ErrorKind::InvalidData, located));
+        }
+
+        unwrap_run(&code, result)
     } else {
-        eval(&prog, &mut stdin().lock(), &mut stdout().lock(), &mut tape, 0x400);
+        let result = eval_state(&prog, &mut input, &mut output, &mut state, &config, &mut ());
+        unwrap_run(&code, result)
+    };
+
+    output.flush().expect("failed to flush output");
+    drop(raw_mode);
+
+    if !done {
+        let steps = if bignum { big_state.steps } else { state.steps };
+        eprintln!("paused after {} steps", steps);
+    }
+
+    if let Some(path) = matches.opt_str("save-state") {
+        let mut file = BufWriter::new(File::create(path).expect("failed to create file"));
+        save(&state, &prog, &mut file).expect("failed to save state");
+    }
+
+    if matches.opt_present("dump-tape") {
+        if bignum {
+            big_state.dump(&mut stderr()).expect("failed to dump tape");
+        } else {
+            dump_tape(&state, &mut stderr()).expect("failed to dump tape");
+        }
+    }
+}
+
+fn show_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    code_options(&mut opts);
+    opts.optflag("", "strict-cells", "show the code generated for strict cells");
+    opts.optopt("", "width", "spread the listing over columns up to this width", "N");
+
+    let matches = parse_args(&opts, args);
+    let usage = "show FILE [options]\n\nPrint the generated code of a program without running it.";
+
+    let (code, _, _) = match read_code(&matches) {
+        Some(code) => code,
+        None => return print_usage(program, usage, &opts),
+    };
+
+    let width = match matches.opt_str("width") {
+        Some(text) => text.parse().expect("invalid width"),
+        None => 120,
+    };
+
+    let (prog, removed) = parse_report(&code, matches.opt_present("strict-cells"));
+    show_code(&prog, width);
+
+    for item in removed.iter() {
+        println!("removed {}: {}", item.inst, item.reason);
+    }
+}
+
+fn check_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    code_options(&mut opts);
+    input_options(&mut opts);
+    opts.optflag("", "portability", "report behaviour that differs between interpreters");
+    opts.optopt("", "max-steps", "stop after this many loop iterations", "N");
+
+    let matches = parse_args(&opts, args);
+    let usage = "check FILE [options]\n\nCheck a program for problems. The exit code is 1 if any were found.";
+
+    let (code, inline, _) = match read_code(&matches) {
+        Some(code) => code,
+        None => return print_usage(program, usage, &opts),
+    };
+
+    if let Err(pos) = match_brackets(&code) {
+        let (line, column) = line_column(&code, pos);
+        println!("unmatched bracket at line {}, column {}", line, column);
+        exit(1);
+    }
+
+    if matches.opt_present("portability") {
+        let mut config = Config { limit: 10_000_000, ..Config::default() };
+
+        if let Some(text) = matches.opt_str("max-steps") {
+            config.limit = text.parse().expect("invalid step count");
+        }
+
+        // a check reads all of its input up front, so stdin has to be asked for
+        let data = read_input(inline, input_sources(&matches));
+
+        let rows = analyze(&code, &data, &config).expect("failed to run program");
+        write_matrix(&rows, &mut stdout()).expect("failed to write report");
+
+        if rows.iter().any(|x| !x.portable) {
+            exit(1);
+        }
+    }
+}
+
+fn lint_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    code_options(&mut opts);
+    opts.optflag("", "bignum", "assume cells of unlimited size without wrapping");
+
+    let matches = parse_args(&opts, args);
+    let usage = "lint FILE [options]\n\n\
+        Warn about code which most likely does not do what it was meant to do.\n\
+        The exit code is 1 if there are any warnings.";
+
+    let (code, _, _) = match read_code(&matches) {
+        Some(code) => code,
+        None => return print_usage(program, usage, &opts),
+    };
+
+    let name = match matches.free.first() {
+        Some(path) if !matches.opt_present("c") => path.as_str(),
+        _ => "-",
+    };
+
+    let warnings = match lint(&code, !matches.opt_present("bignum")) {
+        Ok(warnings) => warnings,
+        Err(pos) => vec![Warning { pos, message: "unmatched bracket".to_string() }],
+    };
+
+    for warning in warnings.iter() {
+        let (line, column) = line_column(&code, warning.pos);
+        println!("{}:{}:{}: {}", name, line, column, warning.message);
+    }
+
+    if !warnings.is_empty() {
+        exit(1);
+    }
+}
+
+// Only the code before the first '!' is formatted with --bang, while the
+// input after it is kept as it is.
+fn format_program(code: &[u8], bang: bool, width: usize) -> Vec<u8> {
+    let (head, tail) = if bang { split_input(code) } else { (code, &[][..]) };
+
+    let mut formatted = match format_source(head, width) {
+        Ok(formatted) => formatted,
+        Err(pos) => {
+            let (line, column) = line_column(code, pos);
+            eprintln!("error: unmatched bracket at line {}, column {}", line, column);
+            exit(1);
+        },
+    };
+
+    if parse(&formatted) != parse(head) {
+        panic!("formatting changed the generated code");
+    }
+
+    if head.len() < code.len() {
+        formatted.push(b'!');
+        formatted.extend_from_slice(tail);
+    }
+
+    formatted
+}
+
+fn fmt_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    code_options(&mut opts);
+    opts.optflag("", "check", "only report files which are not formatted");
+    opts.optopt("", "width", "wrap lines at this width, 80 by default", "N");
+
+    let matches = parse_args(&opts, args);
+    let usage = "fmt FILE... [options]\n\n\
+        Format programs in place. Code given with --cmd or read from stdin\n\
+        using - is printed instead. With --check, nothing is changed and the\n\
+        exit code is 1 if any program is not formatted.";
+
+    if matches.opt_present("h") || matches.free.is_empty() && !matches.opt_present("c") {
+        return print_usage(program, usage, &opts);
+    }
+
+    let width = match matches.opt_str("width") {
+        Some(text) => text.parse().expect("invalid width"),
+        None => 80,
+    };
+
+    let mut programs = Vec::new();
+
+    if let Some(command) = matches.opt_str("c") {
+        programs.push(("-".to_string(), command.into_bytes()));
+    }
+
+    for path in matches.free.iter() {
+        let mut code = Vec::new();
+
+        if path == "-" {
+            stdin().read_to_end(&mut code).expect("failed to read stdin");
+        } else {
+            code = read(path).expect("failed to read file");
+        }
+
+        programs.push((path.clone(), code));
+    }
+
+    let mut unformatted = false;
+
+    for (path, code) in programs.iter() {
+        let formatted = format_program(code, matches.opt_present("b"), width);
+
+        if matches.opt_present("check") {
+            if formatted != *code {
+                println!("{} is not formatted", if path == "-" { "the code" } else { path });
+                unformatted = true;
+            }
+        } else if path == "-" {
+            stdout().write_all(&formatted).expect("failed to write output");
+        } else if formatted != *code {
+            write(path, formatted).expect("failed to write file");
+        }
+    }
+
+    if unformatted {
+        exit(1);
+    }
+}
+
+fn compile_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help message");
+    opts.optopt("c", "cmd", "use code from the command line instead of a file", "CODE");
+    opts.optopt("o", "output-file", "write the result to a file", "FILE");
+    opts.optopt("", "target", "the language to translate to, only c so far", "LANG");
+
+    let matches = parse_args(&opts, args);
+    let usage = "compile FILE [options]\n\nTranslate the generated code of a program into another language.";
+
+    let (code, _, _) = match read_code(&matches) {
+        Some(code) => code,
+        None => return print_usage(program, usage, &opts),
+    };
+
+    if !check_valid(&code) {
+        panic!("invalid code");
+    }
+
+    let target = match matches.opt_str("target") {
+        Some(text) => Target::parse(&text).expect("unknown target"),
+        None => Target::C,
+    };
+
+    let mut output: Box<dyn Write> = match matches.opt_str("o") {
+        Some(path) => Box::new(File::create(path).expect("failed to create file")),
+        None => Box::new(stdout().lock()),
+    };
+
+    let mut prog = parse(&code);
+    evaluate_prefix(&mut prog, &Config::default());
+
+    compile(&prog, target, &mut BufWriter::new(&mut output))
+        .expect("failed to write output");
+}
+
+// Runs a single test and returns why it failed, if it did.
+fn run_test(path: &Path, bang: bool, config: &Config) -> Option<String> {
+    let mut code = match read(path) {
+        Ok(code) => code,
+        Err(err) => return Some(format!("cannot read file: {}", err)),
+    };
+
+    let expected = match read(path.with_extension("out")) {
+        Ok(expected) => expected,
+        Err(_) => return Some("no expected output".to_string()),
+    };
+
+    let mut input = Vec::new();
+
+    if bang {
+        let (head, tail) = split_input(&code);
+        input = tail.to_vec();
+        code.truncate(head.len());
+    }
+
+    if let Ok(data) = read(path.with_extension("in")) {
+        input.extend(data);
+    }
+
+    if !check_valid(&code) {
+        return Some("invalid code".to_string());
+    }
+
+    let prog = parse(&code);
+    let mut state = fresh_state();
+    let mut output = Vec::new();
+
+    match eval_state(&prog, &mut &input[..], &mut output, &mut state, config, &mut ()) {
+        Err(err) => Some(format!("error: {}", err)),
+        Ok(false) => Some(format!("stopped after {} steps", state.steps)),
+
+        Ok(true) if output != expected => {
+            let pos = output.iter().zip(expected.iter())
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| output.len().min(expected.len()));
+
+            Some(format!("output differs at byte {}", pos))
+        },
+
+        Ok(true) => None,
+    }
+}
+
+fn test_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help message");
+    opts.optflag("b", "bang", "use everything after the first '!' as input");
+    opts.optopt("", "max-steps", "fail tests after this many loop iterations", "N");
+
+    let matches = parse_args(&opts, args);
+    let usage = "test FILE... [options]\n\n\
+        Run programs and compare their output with the contents of a file with\n\
+        the same name and the extension .out. If a file with the extension .in\n\
+        exists, it is used as input.";
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        return print_usage(program, usage, &opts);
+    }
+
+    let mut config = Config { limit: 100_000_000, flush: Flush::Never, ..Config::default() };
+
+    if let Some(text) = matches.opt_str("max-steps") {
+        config.limit = text.parse().expect("invalid step count");
+    }
+
+    let mut failed = 0;
+
+    for path in matches.free.iter() {
+        match run_test(Path::new(path), matches.opt_present("b"), &config) {
+            Some(reason) => {
+                println!("test {} ... FAILED, {}", path, reason);
+                failed += 1;
+            },
+            None => println!("test {} ... ok", path),
+        }
+    }
+
+    println!("\n{} passed, {} failed", matches.free.len() - failed, failed);
+
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+fn debug_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    code_options(&mut opts);
+    input_options(&mut opts);
+
+    let matches = parse_args(&opts, args);
+    let usage = "debug FILE [options]\n\n\
+        Step through a program on the source level. Commands for the debugger\n\
+        are read from stdin, so the input of the program has to be given with\n\
+        --input or --input-file.";
+
+    let (code, inline, _) = match read_code(&matches) {
+        Some(code) => code,
+        None => return print_usage(program, usage, &opts),
+    };
+
+    if !check_valid(&code) {
+        panic!("invalid code");
+    }
+
+    let mut sources = input_sources(&matches);
+
+    if sources.contains(&Source::Stdin) {
+        panic!("stdin is needed for debugger commands");
+    }
+
+    sources.insert(0, Source::Text(inline));
+    let mut input = open_inputs(sources).expect("failed to open input");
+
+    let stdin = stdin();
+    let mut debugger = Debugger::new(&code, stdin.lock(), stderr());
+    let mut state = fresh_state();
+
+    eprintln!("rbf debugger, type help for a list of commands");
+    debugger.start(&state).expect("failed to run debugger");
+
+    let result = run(&code, &mut input, &mut stdout(), &mut state, &Config::default(), &mut debugger);
+    let done = unwrap_run(&code, result);
+    debugger.finish().expect("failed to run debugger");
+    stdout().flush().expect("failed to flush output");
+
+    if done {
+        eprintln!("program finished after {} steps", state.steps);
+        eprintln!("{}", tape_window(&state, 4));
+    }
+}
+
+fn bench_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    code_options(&mut opts);
+    input_options(&mut opts);
+    opts.optopt("", "runs", "how often to run the program, 10 by default", "N");
+
+    let matches = parse_args(&opts, args);
+    let usage = "bench FILE [options]\n\n\
+        Measure how long it takes to generate the code of a program and to run\n\
+        it. The output of the program is discarded.";
+
+    let (code, inline, _) = match read_code(&matches) {
+        Some(code) => code,
+        None => return print_usage(program, usage, &opts),
+    };
+
+    if !check_valid(&code) {
+        panic!("invalid code");
+    }
+
+    let runs: u32 = match matches.opt_str("runs") {
+        Some(text) => text.parse().expect("invalid number of runs"),
+        None => 10,
+    };
+
+    let input = read_input(inline, input_sources(&matches));
+
+    let start = Instant::now();
+    let prog = parse(&code);
+    let parse_time = start.elapsed();
+
+    let config = Config { flush: Flush::Never, ..Config::default() };
+    let mut times = Vec::new();
+    let mut steps = 0;
+
+    for _ in 0..runs.max(1) {
+        let mut state = fresh_state();
+
+        let start = Instant::now();
+        let result = eval_state(&prog, &mut &input[..], &mut sink(), &mut state, &config, &mut ());
+        times.push(start.elapsed());
+
+        unwrap_run(&code, result);
+        steps = state.steps;
+    }
+
+    let total: Duration = times.iter().sum();
+    let min = times.iter().min().unwrap();
+    let max = times.iter().max().unwrap();
+
+    println!("parse    {:.3?}", parse_time);
+    println!("run      {:.3?} on average, {:.3?} to {:.3?} over {} runs", total / times.len() as u32, min, max, times.len());
+    println!("steps    {}", steps);
+}
+
+fn repl_command(program: &str, args: &[String]) {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help message");
+
+    let matches = parse_args(&opts, args);
+    let usage = "repl [options]\n\n\
+        Evaluate code line by line on a tape which is kept between lines. Type\n\
+        :help in the repl for a list of its commands.";
+
+    if matches.opt_present("h") || !matches.free.is_empty() {
+        return print_usage(program, usage, &opts);
+    }
+
+    repl().expect("failed to run repl");
+}
+
+fn main() {
+    let args: Vec<String> = args().collect();
+    let program = args.first().unwrap();
+
+    let command = match args.get(1) {
+        Some(command) => command.as_str(),
+        None => return print_help(program),
+    };
+
+    match command {
+        "-h" | "--help" => print_help(program),
+        "run" => run_command(program, &args[2..]),
+        "show" => show_command(program, &args[2..]),
+        "check" => check_command(program, &args[2..]),
+        "lint" => lint_command(program, &args[2..]),
+        "fmt" => fmt_command(program, &args[2..]),
+        "compile" => compile_command(program, &args[2..]),
+        "test" => test_command(program, &args[2..]),
+        "debug" => debug_command(program, &args[2..]),
+        "bench" => bench_command(program, &args[2..]),
+        "repl" => repl_command(program, &args[2..]),
+        _ => run_command(program, &args[1..]),
     }
 }
@@ -0,0 +1,161 @@
+use std::io::{Read, Write, Error, ErrorKind, sink};
+use std::num::Wrapping;
+
+use crate::parser::*;
+use crate::evaluator::*;
+
+pub trait SourceObserver {
+    #[inline]
+    fn command(&mut self, _pos: usize, _state: &State) {}
+
+    // ends the run early, as if the step limit had been reached
+    #[inline]
+    fn stopped(&self) -> bool { false }
+}
+
+impl SourceObserver for () {}
+
+// Unlike eval_state, a program paused by the step limit cannot be resumed.
+pub fn run<R,W,O>(
+    code: &[u8],
+    input: &mut R,
+    output: &mut W,
+    state: &mut State,
+    config: &Config,
+    observer: &mut O
+) -> Result<bool, Error>
+where R: Read, W: Write, O: SourceObserver {
+    let jumps = match_brackets(code).map_err(|pos| Error::new(
+        ErrorKind::InvalidData,
+        format!("unmatched bracket at position {}", pos)
+    ))?;
+
+    let mut buffer = [0u8];
+    let mut index = state.index;
+    let mut pc = 0;
+
+    touch_cell(state, &mut index, 0);
+
+    while let Some(&byte) = code.get(pc) {
+        if observer.stopped() {
+            state.index = index;
+            return Ok(false);
+        }
+
+        if !b"+-<>[].,".contains(&byte) {
+            pc += 1;
+            continue;
+        }
+
+        if config.strict && (byte == b'+' || byte == b'-') {
+            let value = state.tape[index as usize].0 as i32 + if byte == b'+' { 1 } else { -1 };
+            Overflow::check(state, index, value, Some(pc))?;
+        }
+
+        let cell = state.tape.get_mut(index as usize).unwrap();
+        let jump = cell.0 == 0 && byte == b'[' || cell.0 != 0 && byte == b']';
+
+        if cell.0 != 0 && (byte == b'[' || byte == b']') {
+            if state.steps >= config.limit {
+                state.index = index;
+                return Ok(false);
+            }
+
+            state.steps += 1;
+        }
+
+        match byte {
+            b'+' => *cell += Wrapping(1),
+            b'-' => *cell -= Wrapping(1),
+
+            b'>' | b'<' => {
+                index += if byte == b'>' { 1 } else { -1 };
+                touch_cell(state, &mut index, 0);
+            },
+
+            b',' => {
+                match input.read_exact(&mut buffer) {
+                    Ok(()) => state.consumed += 1,
+                    Err(err) if err.kind() == ErrorKind::UnexpectedEof => buffer[0] = config.eof.value(*cell),
+                    Err(err) => return Err(err),
+                }
+
+                *cell = Wrapping(buffer[0]);
+            },
+
+            b'.' => {
+                buffer[0] = cell.0;
+                output.write_all(&buffer)?;
+            },
+
+            _ => if jump {
+                state.index = index;
+                observer.command(pc, state);
+                pc = jumps[pc] + 1;
+                continue;
+            },
+        }
+
+        state.index = index;
+        observer.command(pc, state);
+        pc += 1;
+    }
+
+    Ok(true)
+}
+
+// The optimized code does not know where its instructions came from, so an
+// overflow found there is located by running the source again from the
+// same initial state and input.
+pub fn locate_overflow(code: &[u8], input: &[u8], mut state: State) -> Option<Overflow> {
+    let config = Config { strict: true, ..Config::default() };
+    let mut input = input;
+
+    match run(code, &mut input, &mut sink(), &mut state, &config, &mut ()) {
+        Err(err) => Overflow::find(&err).copied(),
+        Ok(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::empty;
+
+    use crate::naive::*;
+
+    #[test]
+    fn run_simple() {
+        let code = b"+++>--<[>++++<-]++";
+        let mut state = State::default();
+        run(code, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut ()).unwrap();
+        assert_eq!(state.tape, vec![Wrapping(2), Wrapping(10)]);
+    }
+
+    #[test]
+    fn run_hello() {
+        let code = b"+[-->-[>>+>-----<<]<--<---]>-.>>>+.>>..+++[.>]<<<<.+++.------.<<-.>>>>+.";
+        let mut state = State::default();
+        let mut output = Vec::new();
+        run(code, &mut empty(), &mut output, &mut state, &Config::default(), &mut ()).unwrap();
+        assert_eq!(output, b"Hello, World!");
+    }
+
+    #[test]
+    fn run_invalid() {
+        let code = b"+[>+";
+        let mut state = State::default();
+        assert!(run(code, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut ()).is_err());
+    }
+
+    #[test]
+    fn run_strict() {
+        let code = b"++[>+<-]>--\n-";
+        let config = Config { strict: true, ..Config::default() };
+        let mut state = State::default();
+        let err = run(code, &mut empty(), &mut sink(), &mut state, &config, &mut ()).unwrap_err();
+
+        let overflow = Overflow { cell: 1, value: -1, pos: Some(12) };
+        assert_eq!(Overflow::find(&err), Some(&overflow));
+        assert_eq!(locate_overflow(code, b"", State::default()), Some(overflow));
+    }
+}
@@ -6,21 +6,53 @@ use crate::internal::*;
 mod helper;
 use helper::*;
 
-pub fn check_valid(bytes: &[u8]) -> bool {
-    let mut sum = 0;
+mod dead;
+pub use dead::*;
+
+mod known;
+pub use known::*;
 
-    for byte in bytes.iter() {
+pub fn match_brackets(bytes: &[u8]) -> Result<Vec<usize>, usize> {
+    let mut jumps = vec![0; bytes.len()];
+    let mut stack = Vec::new();
+
+    for (pos, byte) in bytes.iter().enumerate() {
         match byte {
-            b'[' => sum += 1,
+            b'[' => stack.push(pos),
             b']' => {
-                sum -= 1;
-                if sum < 0 { return false; }
+                let open = stack.pop().ok_or(pos)?;
+                jumps[open] = pos;
+                jumps[pos] = open;
             },
             _ => (),
         }
     }
 
-    sum == 0
+    match stack.pop() {
+        Some(open) => Err(open),
+        None => Ok(jumps),
+    }
+}
+
+pub fn check_valid(bytes: &[u8]) -> bool {
+    match_brackets(bytes).is_ok()
+}
+
+// Many programs follow the convention that everything after the first `!`
+// is input for the program rather than code.
+pub fn split_input(bytes: &[u8]) -> (&[u8], &[u8]) {
+    match bytes.iter().position(|&x| x == b'!') {
+        Some(pos) => (&bytes[..pos], &bytes[pos + 1..]),
+        None => (bytes, &[]),
+    }
+}
+
+pub fn line_column(bytes: &[u8], pos: usize) -> (usize, usize) {
+    let before = &bytes[..pos.min(bytes.len())];
+    let line = before.iter().filter(|&&x| x == b'\n').count() + 1;
+    let column = pos - before.iter().rposition(|&x| x == b'\n').map_or(0, |x| x + 1) + 1;
+
+    (line, column)
 }
 
 #[inline]
@@ -45,8 +77,28 @@ fn munch_forward(
     sum
 }
 
+// Like `munch_forward`, but stops where the direction changes, since a run
+// like `-+` may pass through an overflow its sum does not show.
+#[inline]
+fn munch_same(bytes: &[u8], index: &mut usize, inc: u8, dec: u8) -> i32 {
+    let first = bytes.get(*index).copied();
+    let mut sum = 0;
+
+    while bytes.get(*index).copied() == first {
+        match first {
+            Some(byte) if byte == inc => sum += 1,
+            Some(byte) if byte == dec => sum -= 1,
+            _ => break,
+        }
+
+        *index += 1;
+    }
+
+    sum
+}
+
 #[inline]
-fn set_touch_inst(inout_list: &mut Vec<IR>) {
+fn set_touch_inst(inout_list: &mut [IR]) {
     let mut upper = 0;
     let mut lower = 0;
 
@@ -60,13 +112,13 @@ fn set_touch_inst(inout_list: &mut Vec<IR>) {
                 lower = 0;
             }
 
-            IR::FixedLoop(_, high, low) => {
+            IR::FixedLoop(_, _, high, low) | IR::If(_, _, high, low) => {
                 upper = max(*high, upper);
                 lower = min(*low, lower);
             },
 
-            IR::Set(off, _) | IR::Add(off, _) | IR::Mul(off, _) |
-            IR::Store(off) | IR::Input(off) | IR::Output(off) => {
+            IR::Set(off, _) | IR::Add(off, _) | IR::Mul(off, _) | IR::Store(off) |
+            IR::Count(off, _) | IR::Input(off) | IR::Output(off) => {
                 upper = max(upper, *off);
                 lower = min(lower, *off);
             }
@@ -83,8 +135,9 @@ fn set_touch_inst(inout_list: &mut Vec<IR>) {
 
 pub fn parse_recursive(
     code: &[u8],
-    mut index: &mut usize,
-    root: bool
+    index: &mut usize,
+    root: bool,
+    strict: bool
 ) -> Vec<IR> {
     let mut prog = Vec::new();
     let mut off_acc = 0isize;
@@ -106,24 +159,33 @@ pub fn parse_recursive(
             },
 
             Some(b'+') | Some(b'-') => {
-                let munch = munch_forward(&code, &mut index, b'+', b'-');
-                let sum = Wrapping(munch as u8);
-
-                add_inst(&mut prog, sum, off_acc);
+                let mut munch = if strict {
+                    munch_same(code, index, b'+', b'-')
+                } else {
+                    munch_forward(code, index, b'+', b'-')
+                };
+
+                // long runs are split up to keep their direction
+                while strict && munch.abs() > 127 {
+                    add_inst(&mut prog, Wrapping((127 * munch.signum()) as u8), off_acc, strict);
+                    munch -= 127 * munch.signum();
+                }
+
+                add_inst(&mut prog, Wrapping(munch as u8), off_acc, strict);
                 continue;
             },
 
             Some(b'<') | Some(b'>') => {
-                let munch = munch_forward(&code, &mut index, b'>', b'<');
+                let munch = munch_forward(code, index, b'>', b'<');
                 off_acc += munch as isize;
                 continue;
             },
 
             Some(b'[') => {
                 *index += 1;
-                let content = parse_recursive(&code, index, false);
+                let content = parse_recursive(code, index, false, strict);
 
-                loop_inst(&mut prog, content, &mut off_acc);
+                loop_inst(&mut prog, content, &mut off_acc, strict);
             },
 
             Some(b']') | None => break,
@@ -143,9 +205,36 @@ pub fn parse_recursive(
     prog
 }
 
+// Parses a whole program and also returns the instructions which were
+// removed as dead code.
+pub fn parse_report(code: &[u8], strict: bool) -> (Vec<IR>, Vec<Removed>) {
+    let mut index = 0;
+    let mut prog = parse_recursive(code, &mut index, true, strict);
+    fold_known_values(&mut prog, strict);
+    let removed = remove_dead_code(&mut prog, true);
+
+    (prog, removed)
+}
+
 pub fn parse(code: &[u8]) -> Vec<IR> {
+    parse_report(code, false).0
+}
+
+// Leaves out every optimization which relies on cells wrapping around, so
+// that a strict evaluation can detect all overflows.
+pub fn parse_strict(code: &[u8]) -> Vec<IR> {
+    parse_report(code, true).0
+}
+
+// Snippets run on a tape which may already be in use, so unlike `parse`
+// this makes no assumptions about the initial cell values and keeps the
+// final pointer movement.
+pub fn parse_snippet(code: &[u8]) -> Vec<IR> {
     let mut index = 0;
-    parse_recursive(code, &mut index, true)
+    let mut prog = parse_recursive(code, &mut index, false, false);
+    fold_known_values(&mut prog, false);
+    remove_dead_code(&mut prog, false);
+    prog
 }
 
 #[cfg(test)]
@@ -176,6 +265,34 @@ mod test {
         assert!(!check_valid(code));
     }
 
+    #[test]
+    fn match_brackets_simple() {
+        let code = b"+[>[-]<]";
+        assert_eq!(match_brackets(code), Ok(vec![0, 7, 0, 5, 0, 3, 0, 1]));
+
+        let code = b"+[>]]";
+        assert_eq!(match_brackets(code), Err(4));
+
+        let code = b"[[]";
+        assert_eq!(match_brackets(code), Err(0));
+    }
+
+    #[test]
+    fn split_input_simple() {
+        assert_eq!(split_input(b",[.,]!hello!"), (&b",[.,]"[..], &b"hello!"[..]));
+        assert_eq!(split_input(b",[.,]"), (&b",[.,]"[..], &b""[..]));
+        assert_eq!(split_input(b"!"), (&b""[..], &b""[..]));
+    }
+
+    #[test]
+    fn line_column_simple() {
+        let code = b"+[\n>+\n\n-]";
+        assert_eq!(line_column(code, 0), (1, 1));
+        assert_eq!(line_column(code, 4), (2, 2));
+        assert_eq!(line_column(code, 2), (1, 3));
+        assert_eq!(line_column(code, 7), (4, 1));
+    }
+
     #[test]
     fn munch_forward_simple() {
         let text = b".....aababba...";
@@ -216,4 +333,40 @@ mod test {
 
         assert_eq!(prog, expected);
     }
+
+    #[test]
+    fn parse_balanced_loop_in_place() {
+        let code = b",>,[<+>.-]<.";
+        let prog = parse(code);
+        let expected = vec![
+            IR::Start,
+            IR::Touch(1, 0),
+            IR::Input(0),
+            IR::Input(1),
+            IR::FixedLoop(vec![
+                IR::Add(0, Wrapping(1)),
+                IR::Output(1),
+                IR::Add(1, Wrapping(255)),
+            ], 1, 1, 0),
+            IR::Output(0),
+        ];
+
+        assert_eq!(prog, expected);
+    }
+
+    #[test]
+    fn parse_snippet_simple() {
+        let code = b"+++[-]>>++[>++++<-]>";
+        let prog = parse_snippet(code);
+        let expected = vec![
+            IR::Touch(3, 0),
+            IR::Set(0, Wrapping(0)),
+            IR::Add(2, Wrapping(2)),
+            IR::Store(2),
+            IR::Mul(3, Wrapping(4)),
+            IR::Move(3),
+        ];
+
+        assert_eq!(prog, expected);
+    }
 }
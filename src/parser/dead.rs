@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use crate::internal::*;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Removed {
+    pub inst: IR,
+    pub reason: &'static str,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Cell {
+    Zero,
+    NonZero,
+    Unknown,
+}
+
+// What is known about the cells around the pointer at some point of a list
+// of instructions.
+#[derive(Clone, Debug)]
+struct Facts {
+    cells: HashMap<isize, Cell>,
+    rest: Cell,
+    // the cells around the pointer which are known to be allocated
+    touched: Option<(isize, isize)>,
+}
+
+impl Facts {
+    fn get(&self, off: isize) -> Cell {
+        *self.cells.get(&off).unwrap_or(&self.rest)
+    }
+
+    fn set(&mut self, off: isize, cell: Cell) {
+        self.cells.insert(off, cell);
+    }
+
+    fn forget(&mut self) {
+        self.cells.clear();
+        self.rest = Cell::Unknown;
+        self.touched = None;
+    }
+}
+
+fn writes(inst: &IR) -> Option<isize> {
+    match inst {
+        IR::Set(off, _) | IR::Add(off, _) | IR::Mul(off, _) |
+        IR::Store(off) | IR::Count(off, _) | IR::Input(off) => Some(*off),
+        _ => None,
+    }
+}
+
+// A loop which neither moves nor changes its own cell never ends once it
+// has been entered.
+fn never_ends(body: &[IR], counter: isize) -> bool {
+    body.iter().all(|x| match x {
+        IR::Touch(_, _) | IR::Output(_) | IR::Print(_) => true,
+        IR::Set(_, _) | IR::Add(_, _) | IR::Mul(_, _) |
+        IR::Store(_) | IR::Count(_, _) | IR::Input(_) => writes(x) != Some(counter),
+        _ => false,
+    })
+}
+
+// The cell of a loop is never 0 when its body starts, but nothing else is
+// known since the body may run any number of times.
+fn loop_entry(counter: isize, touched: Option<(isize, isize)>) -> Facts {
+    let mut facts = Facts { cells: HashMap::new(), rest: Cell::Unknown, touched };
+    facts.set(counter, Cell::NonZero);
+    facts
+}
+
+fn remove_recursive(prog: &mut Vec<IR>, mut facts: Facts, root: bool, removed: &mut Vec<Removed>) {
+    let mut out: Vec<IR> = Vec::with_capacity(prog.len());
+    let mut reachable = true;
+
+    for mut inst in prog.drain(..) {
+        if !reachable {
+            removed.push(Removed { inst, reason: "never reached after an infinite loop" });
+            continue;
+        }
+
+        let current = facts.get(0);
+
+        match &mut inst {
+            IR::Start => {
+                facts.cells.clear();
+                facts.rest = Cell::Zero;
+            },
+
+            IR::Touch(high, low) => {
+                if let Some((lower, upper)) = facts.touched {
+                    if *low >= lower && *high <= upper {
+                        removed.push(Removed { inst, reason: "the cells are already allocated" });
+                        continue;
+                    }
+
+                    facts.touched = Some((lower.min(*low), upper.max(*high)));
+                } else {
+                    facts.touched = Some((*low, *high));
+                }
+            },
+
+            IR::Set(off, val) => {
+                facts.set(*off, if *val == Wrapping(0) { Cell::Zero } else { Cell::NonZero });
+            },
+
+            IR::Add(off, _) => {
+                let cell = if facts.get(*off) == Cell::Zero { Cell::NonZero } else { Cell::Unknown };
+                facts.set(*off, cell);
+            },
+
+            IR::Mul(off, _) | IR::Count(off, _) | IR::Input(off) => facts.set(*off, Cell::Unknown),
+            IR::Store(off) => facts.set(*off, Cell::Zero),
+            IR::Output(_) | IR::Print(_) => (),
+
+            IR::Move(off) => {
+                let off = *off;
+
+                if off == 0 {
+                    removed.push(Removed { inst, reason: "it does not move the pointer" });
+                    continue;
+                }
+
+                facts.cells = facts.cells.drain().map(|(k, v)| (k - off, v)).collect();
+                facts.touched = facts.touched.map(|(low, high)| (low - off, high - off));
+
+                // moves only end up next to each other once the code between
+                // them has been removed
+                if let Some(IR::Move(last)) = out.last_mut() {
+                    removed.push(Removed { inst, reason: "it is merged into the move before it" });
+                    *last += off;
+
+                    if *last == 0 {
+                        let last = out.pop().unwrap();
+                        removed.push(Removed { inst: last, reason: "it does not move the pointer" });
+                    }
+
+                    continue;
+                }
+            },
+
+            IR::Loop(_) | IR::Fill(_, _, _) | IR::Walk(_, _) |
+            IR::Scan(Wrapping(0), _) if current == Cell::Zero => {
+                removed.push(Removed { inst, reason: "the cell is always 0 here" });
+                continue;
+            },
+
+            IR::FixedLoop(_, off, _, _) | IR::If(_, off, _, _) if facts.get(*off) == Cell::Zero => {
+                removed.push(Removed { inst, reason: "the cell is always 0 here" });
+                continue;
+            },
+
+            IR::Loop(body) => {
+                remove_recursive(body, loop_entry(0, None), false, removed);
+                facts.forget();
+                facts.set(0, Cell::Zero);
+            },
+
+            IR::FixedLoop(body, off, high, low) | IR::If(body, off, high, low) => {
+                let off = *off;
+                remove_recursive(body, loop_entry(off, Some((*low, *high))), false, removed);
+
+                reachable = !(facts.get(off) == Cell::NonZero && never_ends(body, off));
+
+                if body.iter().any(|x| matches!(x, IR::FixedLoop(_, _, _, _) | IR::If(_, _, _, _))) {
+                    facts.cells.clear();
+                    facts.rest = Cell::Unknown;
+                }
+
+                for cell in body.iter().filter_map(writes) {
+                    facts.set(cell, Cell::Unknown);
+                }
+
+                facts.set(off, Cell::Zero);
+            },
+
+            IR::Scan(val, _) => {
+                let cell = if *val == Wrapping(0) { Cell::Zero } else { Cell::NonZero };
+                facts.forget();
+                facts.set(0, cell);
+            },
+
+            IR::Fill(_, _, _) | IR::Walk(_, _) => {
+                facts.forget();
+                facts.set(0, Cell::Zero);
+            },
+        }
+
+        out.push(inst);
+    }
+
+    // the pointer is not needed anymore once the program is done
+    if root {
+        while let Some(IR::Move(_)) = out.last() {
+            let inst = out.pop().unwrap();
+            removed.push(Removed { inst, reason: "nothing happens after it" });
+        }
+    }
+
+    *prog = out;
+}
+
+// Removes loops which are never entered, code behind loops which never end
+// and instructions which do nothing. Every loop body is handled without
+// looking at the code around it, the same way it was parsed.
+pub fn remove_dead_code(prog: &mut Vec<IR>, root: bool) -> Vec<Removed> {
+    let mut removed = Vec::new();
+    let entry = Facts { cells: HashMap::new(), rest: Cell::Unknown, touched: None };
+    remove_recursive(prog, entry, root, &mut removed);
+    removed
+}
+
+// Does the same to a loop body as `remove_dead_code` does to the bodies of
+// the loops within a program.
+pub fn remove_dead_code_in_loop(body: &mut Vec<IR>) {
+    remove_recursive(body, loop_entry(0, None), false, &mut Vec::new());
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::*;
+
+    #[test]
+    fn remove_loops_after_loops() {
+        let mut prog = parse_recursive(b",[>,][.][>][-<]>.", &mut 0, true, false);
+        let removed = remove_dead_code(&mut prog, true);
+
+        let loops: Vec<&IR> = removed.iter()
+            .filter(|x| x.reason == "the cell is always 0 here")
+            .map(|x| &x.inst)
+            .collect();
+
+        assert_eq!(loops.len(), 3);
+        assert!(matches!(loops[0], IR::FixedLoop(_, _, _, _)));
+        assert_eq!(loops[1], &IR::Scan(Wrapping(0), 1));
+        assert!(matches!(loops[2], IR::Walk(_, _)));
+        assert!(matches!(prog.last(), Some(IR::Output(1))));
+    }
+
+    #[test]
+    fn remove_code_after_infinite_loop() {
+        let mut prog = parse_recursive(b"+[>.<]>+.", &mut 0, true, false);
+        let removed = remove_dead_code(&mut prog, true);
+
+        assert!(matches!(prog.last(), Some(IR::FixedLoop(_, _, _, _))));
+        assert_eq!(removed, vec![
+            Removed { inst: IR::Add(1, Wrapping(1)), reason: "never reached after an infinite loop" },
+            Removed { inst: IR::Output(1), reason: "never reached after an infinite loop" },
+        ]);
+    }
+}
@@ -21,19 +21,36 @@ pub fn set_inst(out_list: &mut Vec<IR>, sum: Wrapping<u8>, offset: isize) {
 }
 
 #[inline]
-pub fn add_inst(out_list: &mut Vec<IR>, sum: Wrapping<u8>, offset: isize) {
+pub fn add_inst(out_list: &mut Vec<IR>, sum: Wrapping<u8>, offset: isize, strict: bool) {
     if sum == Wrapping(0u8) {
         return;
     }
 
+    // in strict mode, nothing may be folded which would hide an overflow
+    // or make the direction of an add ambiguous
+    let known = |val: Wrapping<u8>| !strict || (0..=255).contains(&(val.0 as i32 + signed(sum)));
+
     for (ind, inst) in out_list.iter_mut().enumerate().rev() {
         match inst {
             IR::Set(off, val) => if *off == offset {
+                if !known(*val) {
+                    break;
+                }
+
                 *val += sum;
                 return;
             },
 
             IR::Add(off, val) => if *off == offset {
+                // adds in opposite directions may hide an overflow between them
+                if strict && (signed(*val) < 0) != (signed(sum) < 0) {
+                    break;
+                }
+
+                if strict && !(-128..=127).contains(&(signed(*val) + signed(sum))) {
+                    break;
+                }
+
                 *val += sum;
                 if *val == Wrapping(0u8) {
                     out_list.remove(ind);
@@ -46,19 +63,27 @@ pub fn add_inst(out_list: &mut Vec<IR>, sum: Wrapping<u8>, offset: isize) {
             }
 
             IR::Store(off) => if *off == offset {
-                out_list.push(IR::Set(offset, sum));
-                return;
+                if known(Wrapping(0u8)) {
+                    out_list.push(IR::Set(offset, sum));
+                    return;
+                }
+
+                break;
             }
 
             IR::Touch(_, _) => (),
 
-            IR::FixedLoop(_, high, low) => {
+            IR::FixedLoop(_, _, high, low) | IR::If(_, _, high, low) => {
                 if offset >= *low && offset <= *high { break; }
             },
 
             IR::Start => {
-                out_list.push(IR::Set(offset, sum));
-                return;
+                if known(Wrapping(0u8)) {
+                    out_list.push(IR::Set(offset, sum));
+                    return;
+                }
+
+                break;
             },
 
             _ => break,
@@ -76,10 +101,29 @@ pub fn move_inst(out_list: &mut Vec<IR>, offset: &mut isize) {
     }
 }
 
+// The body of a balanced loop without its touch, moved to the cell the loop
+// runs on, together with the range of cells it touches from there.
+fn rebased(in_list: &[IR], offset: isize) -> Option<(Vec<IR>, isize, isize)> {
+    if let Some(IR::Touch(high, low)) = in_list.first() {
+        let mut body = in_list[1..].to_vec();
+        rebase(&mut body, offset);
+        return Some((body, *high + offset, *low + offset));
+    }
+
+    None
+}
+
 #[inline]
-fn clear_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize) -> bool {
+fn clear_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize, strict: bool) -> bool {
     if let [IR::Touch(_, _), IR::Add(0, Wrapping(val))] = in_list {
-        if *val & 1 == 1 {
+        // without wrapping, only `[-]` clears a cell, and earlier adds to
+        // it still have to be checked
+        if strict && *val == 255 {
+            out_list.push(IR::Set(*offset, Wrapping(0u8)));
+            return true;
+        }
+
+        if !strict && *val & 1 == 1 {
             set_inst(out_list, Wrapping(0u8), *offset);
             return true;
         }
@@ -89,24 +133,29 @@ fn clear_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize) -> boo
 }
 
 #[inline]
-fn flat_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize) -> bool {
-    let mut sum = Wrapping(1u8);
+fn flat_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize, strict: bool) -> bool {
+    let mut step = Wrapping(0u8);
+    let mut seen = Vec::new();
 
     for inst in in_list.iter().skip(1) {
         match inst {
-            IR::Add(0, val) => { sum += *val },
-            IR::Add(_, _) => (),
+            IR::Add(off, _) if strict && seen.contains(off) => return false,
+            IR::Add(0, val) => { step += *val; seen.push(0) },
+            IR::Add(off, _) => seen.push(*off),
             _ => return false,
         }
     }
 
-    if sum != Wrapping(0u8) {
+    // without wrapping, any other step may end in an overflow instead, and
+    // so may a cell which is changed more than once, like in `[--+]`
+    if step == Wrapping(0u8) || strict && step != Wrapping(255u8) {
         return false;
     }
 
+    // a known counter would be multiplied out at compile time
     let mut imm = None;
 
-    for inst in out_list.iter().rev() {
+    for inst in out_list.iter().rev().take_while(|_| !strict) {
         match inst {
             IR::Set(off, val) if *off == *offset => {
                 imm = Some(*val);
@@ -119,35 +168,63 @@ fn flat_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize) -> bool
         }
     }
 
-    if imm.is_none() {
+    if let Some(cell) = imm {
+        // a counter which never reaches 0 is left to the loop
+        let count = match trip_count(cell, step) {
+            Some(count) => count,
+            None => return false,
+        };
+
+        for inst in in_list.iter() {
+            if let IR::Add(off, val) = inst {
+                if *off != 0 {
+                    add_inst(out_list, *val * count, *offset + *off, strict);
+                }
+            }
+        }
+
+        set_inst(out_list, Wrapping(0u8), *offset);
+        return true;
+    }
+
+    // with an odd step, the number of iterations is the counter times the
+    // inverse of the step, which can be folded into the factors
+    if step.0 & 1 == 1 {
+        let factor = inverse(-step);
         out_list.push(IR::Store(*offset));
+
+        for inst in in_list.iter() {
+            if let IR::Add(off, val) = inst {
+                if *off != 0 {
+                    out_list.push(IR::Mul(*offset + *off, *val * factor));
+                }
+            }
+        }
+
+        return true;
     }
 
+    // an even step only reaches 0 from some counters, otherwise the count
+    // leaves the counter alone and the loop after it never ends
+    out_list.push(IR::Count(*offset, step));
+
     for inst in in_list.iter() {
-        match inst {
-            IR::Add(0, _) => (),
-            IR::Add(off, val) => {
-                let new_off = *offset + *off;
-
-                if let Some(factor) = imm {
-                    add_inst(out_list, *val * factor, new_off);
-                } else {
-                    out_list.push(IR::Mul(new_off, *val))
-                }
-            },
-            _ => (),
+        if let IR::Add(off, val) = inst {
+            if *off != 0 {
+                out_list.push(IR::Mul(*offset + *off, *val));
+            }
         }
     }
 
-    if imm.is_some() {
-        set_inst(out_list, Wrapping(0u8), *offset)
+    if let Some((body, high, low)) = rebased(in_list, *offset) {
+        out_list.push(IR::FixedLoop(body, *offset, high, low));
     }
 
     true
 }
 
 #[inline]
-fn scan_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize) -> bool {
+fn scan_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize, strict: bool) -> bool {
     let mut start_cell = Wrapping(0u8);
     let mut end_cell = Wrapping(0u8);
     let mut set_step = false;
@@ -175,27 +252,69 @@ fn scan_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize) -> bool
         return false;
     }
 
+    let mut start_adds = 0;
+    let mut end_adds = 0;
+
     for inst in in_list.iter() {
         if let IR::Add(off, val) = inst {
             match *off {
-                k if k == 0 => start_cell += *val,
-                k if k == step => end_cell += *val,
+                0 => { start_cell += *val; start_adds += 1 },
+                k if k == step => { end_cell += *val; end_adds += 1 },
                 _ => return false,
             }
         }
     }
 
+    // a cell which is changed more than once may overflow in between
+    if strict && (start_adds > 1 || end_adds > 1) {
+        return false;
+    }
+
     if start_cell + end_cell != Wrapping(0u8) {
         return false;
     }
 
-    add_inst(out_list, start_cell, *offset);
+    // the first add happens even if the loop would not have run, which
+    // must not be reported as an underflow
+    if strict && signed(start_cell) < 0 {
+        return false;
+    }
+
+    add_inst(out_list, start_cell, *offset, strict);
     move_inst(out_list, offset);
 
     out_list.push(IR::Scan(start_cell, step));
     out_list.push(IR::Touch(0, 0));
 
-    add_inst(out_list, end_cell, 0);
+    add_inst(out_list, end_cell, 0, strict);
+
+    true
+}
+
+// Loops which add to the cells around them and then move on, like
+// `[-<+>>]`, stop at the first cell which was 0 to begin with, as long as
+// none of the adds changes a cell before it is checked.
+#[inline]
+fn walk_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: &mut isize, strict: bool) -> bool {
+    let (adds, step) = match in_list {
+        [IR::Touch(_, _), adds @ .., IR::Move(step)] if !strict => (adds, *step),
+        _ => return false,
+    };
+
+    let mut effects = Vec::new();
+
+    for inst in adds.iter() {
+        match inst {
+            IR::Add(off, _) if *off % step == 0 && *off / step > 0 => return false,
+            IR::Add(off, val) => effects.push((*off, *val)),
+            _ => return false,
+        }
+    }
+
+    move_inst(out_list, offset);
+
+    out_list.push(IR::Walk(effects, step));
+    out_list.push(IR::Touch(0, 0));
 
     true
 }
@@ -211,30 +330,69 @@ fn fill_loop(out_list: &mut Vec<IR>, in_list: &[IR]) -> bool {
     false
 }
 
+// A balanced loop whose body always leaves its own cell at 0, like `[-[-]]`
+// or `[>[-]+<[-]]`, runs at most once.
 #[inline]
-fn fixed_loop(out_list: &mut Vec<IR>, in_list: &[IR]) -> bool {
+fn if_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: isize) -> bool {
     let mut pos = 0;
+    let mut cleared = false;
 
     for inst in in_list.iter() {
         match inst {
             IR::Move(off) => pos += *off,
 
-            IR::Loop(_) | IR::Scan(_, _) | IR::Fill(_, _, _)
+            IR::Set(off, val) if pos + *off == 0 => cleared = *val == Wrapping(0u8),
+            IR::Store(off) if pos + *off == 0 => cleared = true,
+
+            IR::Add(off, _) | IR::Mul(off, _) |
+            IR::Count(off, _) | IR::Input(off) if pos + *off == 0 => cleared = false,
+
+            // an inner loop on the same cell only ends once it is 0
+            IR::FixedLoop(_, off, _, _) | IR::If(_, off, _, _) if pos + *off == 0 => cleared = true,
+
+            IR::FixedLoop(_, _, high, low) | IR::If(_, _, high, low)
+                if pos + *low <= 0 && pos + *high >= 0 => cleared = false,
+
+            IR::Loop(_) | IR::Scan(_, _) | IR::Fill(_, _, _) | IR::Walk(_, _)
                 => return false,
 
             _ => (),
         }
     }
 
-    if pos != 0 {
+    if pos != 0 || !cleared {
         return false;
     }
 
-    if let Some(IR::Touch(high, low)) = in_list.first() {
-        let tail: Vec<IR> = in_list.to_vec()
-            .into_iter().skip(1).collect::<Vec<IR>>();
+    if let Some((body, high, low)) = rebased(in_list, offset) {
+        out_list.push(IR::If(body, offset, high, low));
+        return true;
+    }
+
+    false
+}
+
+#[inline]
+fn fixed_loop(out_list: &mut Vec<IR>, in_list: &[IR], offset: isize) -> bool {
+    let mut pos = 0;
+
+    for inst in in_list.iter() {
+        match inst {
+            IR::Move(off) => pos += *off,
+
+            IR::Loop(_) | IR::Scan(_, _) | IR::Fill(_, _, _) | IR::Walk(_, _)
+                => return false,
+
+            _ => (),
+        }
+    }
 
-        out_list.push(IR::FixedLoop(tail, *high, *low));
+    if pos != 0 {
+        return false;
+    }
+
+    if let Some((body, high, low)) = rebased(in_list, offset) {
+        out_list.push(IR::FixedLoop(body, offset, high, low));
         return true;
     }
 
@@ -242,7 +400,7 @@ fn fixed_loop(out_list: &mut Vec<IR>, in_list: &[IR]) -> bool {
 }
 
 #[inline]
-pub fn loop_inst(out_list: &mut Vec<IR>, in_list: Vec<IR>, offset: &mut isize) {
+pub fn loop_inst(out_list: &mut Vec<IR>, in_list: Vec<IR>, offset: &mut isize, strict: bool) {
     for inst in out_list.iter().rev() {
         if let IR::Set(off, _) | IR::Add(off, _) | IR::Mul(off, _)
              | IR::Store(off) | IR::Input(off) | IR::Output(off) = inst {
@@ -256,7 +414,7 @@ pub fn loop_inst(out_list: &mut Vec<IR>, in_list: Vec<IR>, offset: &mut isize) {
             IR::Set(_, _) => break,
             IR::Store(_) => return,
 
-            IR::FixedLoop(_, high, low) => {
+            IR::FixedLoop(_, _, high, low) | IR::If(_, _, high, low) => {
                 if *offset >= *low && *offset <= *high { break; }
             },
 
@@ -266,23 +424,32 @@ pub fn loop_inst(out_list: &mut Vec<IR>, in_list: Vec<IR>, offset: &mut isize) {
         }
     }
 
-    if clear_loop(out_list, &in_list, offset) { return; }
-    if flat_loop(out_list, &in_list, offset) { return; }
-    if scan_loop(out_list, &in_list, offset) { return; }
+    if clear_loop(out_list, &in_list, offset, strict) { return; }
+    if flat_loop(out_list, &in_list, offset, strict) { return; }
+    if scan_loop(out_list, &in_list, offset, strict) { return; }
+    if walk_loop(out_list, &in_list, offset, strict) { return; }
+
+    // loops which do not move the pointer run where it would be, so the
+    // move can wait until the pointer really changes
+    if if_loop(out_list, &in_list, *offset) { return; }
+    if fixed_loop(out_list, &in_list, *offset) { return; }
 
     move_inst(out_list, offset);
 
     if fill_loop(out_list, &in_list) { return; }
-    if fixed_loop(out_list, &in_list) { return; }
 
+    // the loop may leave the pointer on a cell which does not exist yet
     out_list.push(IR::Loop(in_list));
+    out_list.push(IR::Touch(0, 0));
 }
 
 #[cfg(test)]
 mod test {
     use std::num::Wrapping;
     use std::collections::HashSet;
+    use std::io::sink;
     use crate::internal::IR;
+    use crate::evaluator::*;
     use super::*;
 
     #[test]
@@ -316,9 +483,78 @@ mod test {
             let in_list = vec![IR::Touch(0, 0), IR::Add(0, step)];
             let mut out_list = Vec::new();
             let mut offset = 0;
-            let does_clear = clear_loop(&mut out_list, &in_list, &mut offset);
+            let does_clear = clear_loop(&mut out_list, &in_list, &mut offset, false);
 
             assert_eq!(should_clear, does_clear);
         }
     }
+
+    #[test]
+    fn flat_loop_any_step() {
+        let config = Config { limit: 1000, ..Config::default() };
+
+        for &step in [1u8, 3, 255, 253, 254, 252, 128].iter() {
+            let in_list = vec![IR::Touch(1, 0), IR::Add(0, Wrapping(step)), IR::Add(1, Wrapping(5))];
+
+            for cell in 0..=255u8 {
+                let mut counter = Wrapping(cell);
+                let mut target = Wrapping(0u8);
+                let mut runs = 0;
+
+                while counter.0 != 0 && runs < 256 {
+                    counter += Wrapping(step);
+                    target += Wrapping(5);
+                    runs += 1;
+                }
+
+                let ends = counter.0 == 0;
+
+                for &known in [false, true].iter() {
+                    let mut out_list = vec![IR::Touch(1, 0), IR::Set(0, Wrapping(cell))];
+                    let mut offset = 0;
+
+                    if !known {
+                        out_list[1] = IR::Input(0);
+                    }
+
+                    if !flat_loop(&mut out_list, &in_list, &mut offset, false) {
+                        assert!(known && !ends);
+                        continue;
+                    }
+
+                    let mut state = State::default();
+                    let mut input: &[u8] = &[cell];
+                    let done = eval_state(&out_list, &mut input, &mut sink(), &mut state, &config, &mut ()).unwrap();
+
+                    assert_eq!(done, ends, "{} {}", step, cell);
+
+                    if ends {
+                        assert_eq!(state.tape, vec![Wrapping(0), target], "{} {}", step, cell);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn if_loop_runs_once() {
+        let cases: [(&[IR], bool); 5] = [
+            (&[IR::Touch(1, 0), IR::Add(0, Wrapping(255)), IR::Set(0, Wrapping(0)), IR::Add(1, Wrapping(1))], true),
+            (&[IR::Touch(1, 0), IR::Add(1, Wrapping(1)), IR::Store(0), IR::Mul(1, Wrapping(1))], true),
+            (&[IR::Touch(1, 0), IR::FixedLoop(vec![IR::Add(1, Wrapping(255))], 1, 1, 1)], false),
+            (&[IR::Touch(0, 0), IR::Set(0, Wrapping(0)), IR::Add(0, Wrapping(1))], false),
+            (&[IR::Touch(1, -1), IR::Move(1), IR::Set(-1, Wrapping(0)), IR::Move(-1)], true),
+        ];
+
+        for (in_list, expected) in cases.iter() {
+            let mut out_list = Vec::new();
+            assert_eq!(if_loop(&mut out_list, in_list, 2), *expected, "{:?}", in_list);
+
+            if *expected {
+                let mut body = in_list[1..].to_vec();
+                rebase(&mut body, 2);
+                assert!(matches!(out_list.as_slice(), [IR::If(sub, 2, 3, _)] if *sub == body));
+            }
+        }
+    }
 }
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use crate::internal::*;
+
+// The values of cells and of the register which are known at some point of
+// a list of instructions, where `None` stands for any value.
+#[derive(Clone, Debug)]
+struct Known {
+    cells: HashMap<isize, Option<Wrapping<u8>>>,
+    rest: Option<Wrapping<u8>>,
+    register: Option<Wrapping<u8>>,
+}
+
+impl Known {
+    fn unknown() -> Self {
+        Known { cells: HashMap::new(), rest: None, register: None }
+    }
+
+    fn get(&self, off: isize) -> Option<Wrapping<u8>> {
+        *self.cells.get(&off).unwrap_or(&self.rest)
+    }
+
+    fn set(&mut self, off: isize, val: Option<Wrapping<u8>>) {
+        self.cells.insert(off, val);
+    }
+}
+
+// The offsets of all cells a balanced loop body may change.
+fn written(body: &[IR], offsets: &mut Vec<isize>) {
+    for inst in body.iter() {
+        match inst {
+            IR::Set(off, _) | IR::Add(off, _) | IR::Mul(off, _) |
+            IR::Store(off) | IR::Count(off, _) | IR::Input(off) => offsets.push(*off),
+            IR::FixedLoop(sub, _, _, _) | IR::If(sub, _, _, _) => written(sub, offsets),
+            _ => (),
+        }
+    }
+}
+
+fn fold_recursive(prog: &mut Vec<IR>, known: &mut Known, strict: bool) {
+    let mut out: Vec<IR> = Vec::with_capacity(prog.len());
+
+    for mut inst in prog.drain(..) {
+        match &mut inst {
+            IR::Start => {
+                known.cells.clear();
+                known.rest = Some(Wrapping(0));
+            },
+
+            IR::Set(off, val) => {
+                known.set(*off, Some(*val));
+            },
+
+            // in strict mode, an add which overflows has to stay
+            IR::Add(off, val) => match known.get(*off) {
+                Some(cell) if !strict || (0..=255).contains(&(cell.0 as i32 + signed(*val))) => {
+                    let result = cell + *val;
+
+                    if let Some(IR::Set(last, _)) = out.last() {
+                        if *last == *off {
+                            out.pop();
+                        }
+                    }
+
+                    known.set(*off, Some(result));
+                    out.push(IR::Set(*off, result));
+                    continue;
+                },
+
+                _ => known.set(*off, None),
+            },
+
+            IR::Mul(off, val) => match known.register {
+                Some(Wrapping(0)) => continue,
+
+                Some(register) if !strict || (-128..=127).contains(&(register.0 as i32 * signed(*val))) => {
+                    let mut add = vec![IR::Add(*off, register * *val)];
+                    fold_recursive(&mut add, known, strict);
+                    out.append(&mut add);
+                    continue;
+                },
+
+                _ => known.set(*off, None),
+            },
+
+            IR::Store(off) => {
+                known.register = known.get(*off);
+                known.set(*off, Some(Wrapping(0)));
+            },
+
+            IR::Count(off, step) => match known.get(*off).map(|x| (x, trip_count(x, *step))) {
+                Some((_, Some(count))) => {
+                    known.register = Some(count);
+                    known.set(*off, Some(Wrapping(0)));
+                },
+
+                Some((cell, None)) => {
+                    known.register = Some(Wrapping(0));
+                    known.set(*off, Some(cell));
+                },
+
+                None => {
+                    known.register = None;
+                    known.set(*off, None);
+                },
+            },
+
+            IR::Move(off) => {
+                let off = *off;
+                known.cells = known.cells.drain().map(|(k, v)| (k - off, v)).collect();
+            },
+
+            IR::Input(off) => known.set(*off, None),
+            IR::Touch(_, _) | IR::Output(_) | IR::Print(_) => (),
+
+            // a loop which never runs changes nothing, and the dead code
+            // pass will remove it
+            IR::Loop(_) | IR::Fill(_, _, _) | IR::Walk(_, _) if known.get(0) == Some(Wrapping(0)) => (),
+
+            IR::FixedLoop(_, off, _, _) | IR::If(_, off, _, _) if known.get(*off) == Some(Wrapping(0)) => (),
+
+            IR::Scan(val, _) if known.get(0) == Some(*val) => (),
+
+            IR::Loop(body) => {
+                fold_recursive(body, &mut Known::unknown(), strict);
+
+                *known = Known::unknown();
+                known.set(0, Some(Wrapping(0)));
+            },
+
+            // the cells a balanced loop does not touch keep their values
+            IR::FixedLoop(body, off, _, _) | IR::If(body, off, _, _) => {
+                fold_recursive(body, &mut Known::unknown(), strict);
+
+                let mut offsets = Vec::new();
+                written(body, &mut offsets);
+
+                for cell in offsets {
+                    known.set(cell, None);
+                }
+
+                known.set(*off, Some(Wrapping(0)));
+                known.register = None;
+            },
+
+            IR::Scan(val, _) => {
+                let val = *val;
+                *known = Known::unknown();
+                known.set(0, Some(val));
+            },
+
+            IR::Fill(_, _, _) | IR::Walk(_, _) => {
+                *known = Known::unknown();
+                known.set(0, Some(Wrapping(0)));
+            },
+        }
+
+        out.push(inst);
+    }
+
+    *prog = out;
+}
+
+// Follows the values of cells through the whole program and uses them to
+// turn adds into sets and multiplications with a known register into adds.
+// Loops which are never entered are left for the dead code pass. Loop
+// bodies start without any knowledge, the same way they were parsed.
+pub fn fold_known_values(prog: &mut Vec<IR>, strict: bool) {
+    fold_recursive(prog, &mut Known::unknown(), strict);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::*;
+
+    #[test]
+    fn fold_across_loops() {
+        let mut prog = parse_recursive(b"++>>+<<[>>.<<-]>>+[<+++>-]<.", &mut 0, true, false);
+        fold_known_values(&mut prog, false);
+
+        assert_eq!(&prog[5..], &[
+            IR::Set(2, Wrapping(2)),
+            IR::Store(2),
+            IR::Set(1, Wrapping(6)),
+            IR::Output(1),
+        ]);
+
+        let mut prog = parse_recursive(b"+[<]+.", &mut 0, true, false);
+        fold_known_values(&mut prog, false);
+
+        assert!(prog.ends_with(&[
+            IR::Scan(Wrapping(0), -1),
+            IR::Touch(0, 0),
+            IR::Set(0, Wrapping(1)),
+            IR::Output(0),
+        ]));
+    }
+
+    #[test]
+    fn fold_keeps_overflows() {
+        let mut prog = parse_recursive(b"++++++++++++++++[>++++++++++++++++<-]+>-", &mut 0, true, true);
+        fold_known_values(&mut prog, true);
+
+        assert!(prog.contains(&IR::Mul(1, Wrapping(16))));
+        assert!(prog.contains(&IR::Add(1, Wrapping(255))));
+        assert!(prog.contains(&IR::Set(0, Wrapping(1))));
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::{Write, Error};
+
+use crate::parser::*;
+use crate::evaluator::*;
+use crate::naive::*;
+use crate::bignum::*;
+use crate::streams::*;
+
+// The tape size most interpreters have copied from the original one.
+const TAPE: isize = 30000;
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+struct Tracker {
+    code: Vec<u8>,
+    wraps: u64,
+    first_wrap: Option<usize>,
+    leftmost: isize,
+    first_left: Option<usize>,
+    rightmost: isize,
+    eof_reads: u64,
+    first_eof: Option<usize>,
+    consumed: u64,
+}
+
+impl SourceObserver for Tracker {
+    fn command(&mut self, pos: usize, state: &State) {
+        let ptr = state.index - state.origin;
+        let val = state.tape.get(state.index as usize).map_or(0, |x| x.0);
+
+        match self.code[pos] {
+            b'+' | b'-' if val == if self.code[pos] == b'+' { 0 } else { 255 } => {
+                self.wraps += 1;
+                self.first_wrap.get_or_insert(pos);
+            },
+
+            b'<' if ptr < 0 => {
+                self.first_left.get_or_insert(pos);
+                self.leftmost = self.leftmost.min(ptr);
+            },
+
+            b'>' => self.rightmost = self.rightmost.max(ptr),
+
+            b',' => {
+                if state.consumed == self.consumed {
+                    self.eof_reads += 1;
+                    self.first_eof.get_or_insert(pos);
+                }
+
+                self.consumed = state.consumed;
+            },
+
+            _ => (),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Row {
+    pub check: &'static str,
+    pub portable: bool,
+    pub details: String,
+}
+
+fn times(count: u64) -> String {
+    match count {
+        1 => "once".to_string(),
+        n => format!("{} times", n),
+    }
+}
+
+fn location(code: &[u8], pos: Option<usize>) -> String {
+    let (line, column) = line_column(code, pos.unwrap_or_default());
+    format!("first at line {}, column {}", line, column)
+}
+
+fn run_with(code: &[u8], input: &[u8], config: &Config) -> Result<(Vec<u8>, bool), Error> {
+    let mut state = State::default();
+    let mut output = Vec::new();
+    let done = run(code, &mut &input[..], &mut output, &mut state, config, &mut ())?;
+    Ok((output, done))
+}
+
+// Runs the program on the given input once while watching for behaviour
+// that differs between interpreters, then runs it again under the other
+// common conventions to see whether that changes the output.
+pub fn analyze(code: &[u8], input: &[u8], config: &Config) -> Result<Vec<Row>, Error> {
+    let mut tracker = Tracker { code: code.to_vec(), ..Tracker::default() };
+    let mut state = State::default();
+    let mut output = Vec::new();
+    let done = run(code, &mut &input[..], &mut output, &mut state, config, &mut tracker)?;
+
+    let mut rows = Vec::new();
+
+    rows.push(Row {
+        check: "finished",
+        portable: done,
+        details: match done {
+            true => format!("after {} loop iterations", state.steps),
+            false => format!("stopped after {} loop iterations, results are incomplete", state.steps),
+        },
+    });
+
+    rows.push(Row {
+        check: "wrap-around",
+        portable: tracker.wraps == 0,
+        details: match tracker.wraps {
+            0 => "cells stay within 0 to 255".to_string(),
+            n => format!("{}, {}", times(n), location(code, tracker.first_wrap)),
+        },
+    });
+
+    rows.push(Row {
+        check: "left of start",
+        portable: tracker.first_left.is_none(),
+        details: match tracker.first_left {
+            None => "never moves left of the first cell".to_string(),
+            pos => format!("reaches cell {}, {}", tracker.leftmost, location(code, pos)),
+        },
+    });
+
+    rows.push(Row {
+        check: "tape size",
+        portable: tracker.rightmost < TAPE,
+        details: format!("uses {} of {} cells", tracker.rightmost + 1, TAPE),
+    });
+
+    // a program which does not depend on the conventions needs exactly as
+    // many steps under all of them, so the other runs can be cut short
+    let limit = config.limit.min(state.steps + 1);
+    let mut differs = Vec::new();
+
+    if tracker.eof_reads > 0 {
+        for (eof, name) in [(Eof::Max, "255"), (Eof::Unchanged, "unchanged")].iter() {
+            let other = run_with(code, input, &Config { eof: *eof, limit, ..*config })?;
+
+            if other != (output.clone(), done) {
+                differs.push(*name);
+            }
+        }
+    }
+
+    rows.push(Row {
+        check: "end of input",
+        portable: differs.is_empty(),
+        details: match (tracker.eof_reads, differs.is_empty()) {
+            (0, _) => "never reads past the end of the input".to_string(),
+            (n, true) => format!("reads past the end {}, output does not depend on it", times(n)),
+            (n, false) => format!(
+                "reads past the end {}, output changes if it reads as {}",
+                times(n),
+                differs.join(" or ")
+            ),
+        },
+    });
+
+    // unbounded cells may need fewer steps, since more loops are multiplied
+    // out, but never more
+    let prog = parse_big(code)?;
+    let mut wide = BigState::default();
+    let mut wide_output = Vec::new();
+    let wide_config = Config { limit, flush: Flush::Never, ..*config };
+    let wide_done = eval_big(&prog, input, &mut wide_output, &mut wide, &wide_config, &Format::default())?;
+
+    let same = wide_done && wide_output == output;
+
+    rows.push(Row {
+        check: "cell width",
+        portable: same || !done,
+        details: match (same, wide_done) {
+            (true, _) => "same output with unbounded cells".to_string(),
+            (false, true) => "output changes with wider cells".to_string(),
+            (false, false) => "does not finish with wider cells".to_string(),
+        },
+    });
+
+    Ok(rows)
+}
+
+pub fn write_matrix<W: Write>(rows: &[Row], output: &mut W) -> Result<(), Error> {
+    writeln!(output, "{:<15} {:<8} details", "check", "result")?;
+
+    for row in rows.iter() {
+        let result = if row.portable { "ok" } else { "differs" };
+        writeln!(output, "{:<15} {:<8} {}", row.check, result, row.details)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::portability::*;
+
+    fn results(code: &[u8], input: &[u8]) -> Vec<(&'static str, bool)> {
+        analyze(code, input, &Config::default()).unwrap()
+            .into_iter()
+            .map(|x| (x.check, x.portable))
+            .collect()
+    }
+
+    #[test]
+    fn portable_program() {
+        let rows = results(b",.>,.>+++[>++++<-]>.", b"ab");
+        assert!(rows.iter().all(|x| x.1), "{:?}", rows);
+    }
+
+    #[test]
+    fn wrapping_program() {
+        let rows = results(b"<+[+]", b"");
+
+        assert_eq!(rows, vec![
+            ("finished", true),
+            ("wrap-around", false),
+            ("left of start", false),
+            ("tape size", true),
+            ("end of input", true),
+            ("cell width", false),
+        ]);
+    }
+
+    #[test]
+    fn eof_dependent_program() {
+        let rows = analyze(b",[.,]", b"ab", &Config::default()).unwrap();
+        let eof = rows.iter().find(|x| x.check == "end of input").unwrap();
+
+        assert!(!eof.portable);
+        assert!(eof.details.ends_with("reads as 255 or unchanged"), "{}", eof.details);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::empty;
+use std::num::Wrapping;
+
+use crate::internal::*;
+use crate::evaluator::*;
+
+// Loop iterations which may be spent on a prefix before it is left for the
+// actual run.
+pub const PREFIX_STEPS: u64 = 1_000_000;
+
+fn reads_input(inst: &IR) -> bool {
+    match inst {
+        IR::Input(_) => true,
+        IR::Loop(sub) | IR::FixedLoop(sub, _, _, _) | IR::If(sub, _, _, _) => sub.iter().any(reads_input),
+        _ => false,
+    }
+}
+
+// Runs a program on a fresh tape up to its first input, or until the loop
+// iterations run out, and replaces everything which ran with the output it
+// printed and the tape it left behind. Returns the number of loop
+// iterations which have been spent, so that the caller can count them.
+pub fn evaluate_prefix(prog: &mut Vec<IR>, config: &Config) -> u64 {
+    if prog.first() != Some(&IR::Start) {
+        return 0;
+    }
+
+    let config = Config { limit: config.limit.min(PREFIX_STEPS), ..*config };
+    let mut state = State::default();
+    let mut output = Vec::new();
+    let mut end = 0;
+
+    while end < prog.len() && !reads_input(&prog[end]) {
+        // the register does not outlive a call to the evaluator, so the code
+        // runs in pieces which each end with a loop
+        let mut next = end;
+
+        while next < prog.len() && !reads_input(&prog[next]) {
+            next += 1;
+
+            if let IR::Loop(_) | IR::FixedLoop(_, _, _, _) | IR::If(_, _, _, _) = prog[next - 1] {
+                break;
+            }
+        }
+
+        let saved = (state.clone(), output.len());
+
+        match eval_recursive(&prog[end..next], &mut empty(), &mut output, &mut state, &[], &config, &mut ()) {
+            Ok(true) => end = next,
+
+            _ => {
+                state = saved.0;
+                output.truncate(saved.1);
+                break;
+            },
+        }
+    }
+
+    // the program is left alone if nothing but the start could be done
+    if end <= 2 {
+        return 0;
+    }
+
+    let mut init = vec![IR::Start];
+
+    if !output.is_empty() {
+        init.push(IR::Print(output));
+    }
+
+    let origin = state.origin;
+    init.push(IR::Touch(state.tape.len() as isize - 1 - origin, -origin));
+
+    for (pos, cell) in state.tape.iter().enumerate() {
+        if *cell != Wrapping(0) {
+            init.push(IR::Set(pos as isize - origin, *cell));
+        }
+    }
+
+    if state.index != origin {
+        init.push(IR::Move(state.index - origin));
+    }
+
+    prog.splice(..end, init);
+    state.steps
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::sink;
+
+    use crate::parser::*;
+    use crate::prefix::*;
+
+    #[test]
+    fn prefix_hello() {
+        let code = b"+[-->-[>>+>-----<<]<--<---]>-.>>>+.>>..+++[.>]<<<<.+++.------.<<-.>>>>+.";
+        let mut prog = parse(code);
+        let steps = evaluate_prefix(&mut prog, &Config::default());
+
+        assert!(steps > 0);
+        assert_eq!(prog[1], IR::Print(b"Hello, World!".to_vec()));
+        assert!(!prog.iter().any(|x| matches!(x, IR::Loop(_) | IR::FixedLoop(_, _, _, _) | IR::Output(_))));
+    }
+
+    #[test]
+    fn prefix_keeps_tape() {
+        let code = b"++++++[>++++++++<-]>+.<+++[>+<-]>.,[.,]";
+        let mut prog = parse(code);
+        evaluate_prefix(&mut prog, &Config::default());
+
+        assert_eq!(&prog[..5], &[
+            IR::Start,
+            IR::Print(b"14".to_vec()),
+            IR::Touch(1, 0),
+            IR::Set(1, Wrapping(52)),
+            IR::Input(1),
+        ]);
+
+        let mut input: &[u8] = b"ab";
+        let mut output = Vec::new();
+        let mut state = State::default();
+        eval_state(&prog, &mut input, &mut output, &mut state, &Config::default(), &mut ()).unwrap();
+        assert_eq!(output, b"14ab");
+    }
+
+    #[test]
+    fn prefix_step_budget() {
+        let code = b"+[>+<]";
+        let mut prog = parse(code);
+
+        let config = Config { limit: 100, ..Config::default() };
+        assert_eq!(evaluate_prefix(&mut prog, &config), 0);
+        assert!(matches!(prog.last(), Some(IR::FixedLoop(_, _, _, _))));
+
+        let mut state = State::default();
+        let done = eval_state(&prog, &mut empty(), &mut sink(), &mut state, &config, &mut ()).unwrap();
+        assert!(!done);
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use crate::internal::*;
+use crate::parser::*;
+use crate::evaluator::*;
+
+#[derive(Default)]
+pub struct Profile {
+    counts: HashMap<*const IR, u64>,
+    iterations: HashMap<*const IR, u64>,
+}
+
+impl Observer for Profile {
+    fn inst(&mut self, inst: &IR, _state: &State) {
+        *self.counts.entry(inst as *const IR).or_insert(0) += 1;
+    }
+
+    fn iteration(&mut self, inst: &IR, _state: &State) {
+        *self.iterations.entry(inst as *const IR).or_insert(0) += 1;
+    }
+}
+
+struct LoopStats<'a> {
+    inst: &'a IR,
+    span: Option<(usize, usize)>,
+    entries: u64,
+    iterations: u64,
+    innermost: bool,
+}
+
+fn bracket_pairs(code: &[u8]) -> Vec<(usize, usize)> {
+    let mut stack = Vec::new();
+    let mut pairs = Vec::new();
+
+    for (pos, byte) in code.iter().enumerate() {
+        match byte {
+            b'[' => stack.push(pos),
+            b']' => if let Some(open) = stack.pop() {
+                pairs.push((open, pos));
+            },
+            _ => (),
+        }
+    }
+
+    pairs.sort_unstable();
+    pairs
+}
+
+// The body of a loop the way it was parsed, which for balanced loops means
+// moving it back from the cell they run on.
+fn loop_body(inst: &IR) -> Option<Vec<IR>> {
+    match inst {
+        IR::Loop(sub) => Some(sub.clone()),
+        IR::FixedLoop(sub, off, high, low) | IR::If(sub, off, high, low) => {
+            let mut body = vec![IR::Touch(*high, *low)];
+            body.extend(sub.iter().cloned());
+            rebase(&mut body, -*off);
+            Some(body)
+        },
+        _ => None,
+    }
+}
+
+// Loop bodies are parsed without any outside context, so every loop left
+// in the program can be found again by parsing the contents of each pair
+// of brackets and comparing the results in source order.
+struct SpanMatcher<'a> {
+    code: &'a [u8],
+    pairs: Vec<(usize, usize)>,
+    bodies: HashMap<usize, Vec<IR>>,
+    cursor: usize,
+}
+
+impl<'a> SpanMatcher<'a> {
+    fn new(code: &'a [u8]) -> Self {
+        SpanMatcher {
+            code,
+            pairs: bracket_pairs(code),
+            bodies: HashMap::new(),
+            cursor: 0,
+        }
+    }
+
+    fn find(&mut self, body: &[IR]) -> Option<(usize, usize)> {
+        for k in self.cursor..self.pairs.len() {
+            let (open, close) = self.pairs[k];
+            let code = self.code;
+
+            let parsed = self.bodies.entry(k).or_insert_with(|| {
+                let mut index = open + 1;
+                let mut body = parse_recursive(code, &mut index, false, false);
+                fold_known_values(&mut body, false);
+                remove_dead_code_in_loop(&mut body);
+                body
+            });
+
+            if parsed.as_slice() == body {
+                self.cursor = k + 1;
+                return Some((open, close));
+            }
+        }
+
+        None
+    }
+}
+
+fn collect_loops<'a>(
+    prog: &'a [IR],
+    profile: &Profile,
+    matcher: &mut SpanMatcher,
+    loops: &mut Vec<LoopStats<'a>>,
+    mix: &mut HashMap<String, u64>,
+) {
+    for inst in prog.iter() {
+        let ptr = inst as *const IR;
+        let count = profile.counts.get(&ptr).copied().unwrap_or_default();
+
+        let kind = inst.to_string();
+        let kind = kind.split_whitespace().next().unwrap_or_default();
+        *mix.entry(kind.to_string()).or_insert(0) += count;
+
+        if let IR::Loop(sub) | IR::FixedLoop(sub, _, _, _) | IR::If(sub, _, _, _) = inst {
+            let innermost = !sub.iter()
+                .any(|x| matches!(x, IR::Loop(_) | IR::FixedLoop(_, _, _, _) | IR::If(_, _, _, _)));
+
+            loops.push(LoopStats {
+                inst,
+                span: loop_body(inst).and_then(|body| matcher.find(&body)),
+                entries: count,
+                iterations: profile.iterations.get(&ptr).copied().unwrap_or_default(),
+                innermost,
+            });
+
+            collect_loops(sub, profile, matcher, loops, mix);
+        }
+    }
+}
+
+fn diagnose(inst: &IR) -> String {
+    let sub = match loop_body(inst) {
+        Some(body) => body,
+        None => return String::new(),
+    };
+
+    if sub.iter().any(|x| matches!(x, IR::Input(_) | IR::Output(_) | IR::Print(_))) {
+        return "performs i/o".to_string();
+    }
+
+    if sub.iter().any(|x| matches!(x, IR::Scan(_, _) | IR::Fill(_, _, _) | IR::Walk(_, _))) {
+        return "contains a scan".to_string();
+    }
+
+    let step: isize = sub.iter()
+        .map(|x| if let IR::Move(off) = x { *off } else { 0 })
+        .sum();
+
+    if step != 0 {
+        return format!("moves the pointer by {:+} per iteration", step);
+    }
+
+    if sub.iter().any(|x| matches!(x, IR::Set(0, _) | IR::Mul(0, _) | IR::Store(0) | IR::Count(0, _))) {
+        return "overwrites its own counter".to_string();
+    }
+
+    let counter: Wrapping<u8> = sub.iter()
+        .map(|x| if let IR::Add(0, val) = x { *val } else { Wrapping(0) })
+        .sum();
+
+    format!("changes its counter by {} per iteration", counter)
+}
+
+fn span_text(code: &[u8], span: Option<(usize, usize)>) -> String {
+    match span {
+        Some((open, close)) => {
+            let snippet: String = code[open..=close].iter()
+                .filter(|x| b"+-<>[].,".contains(x))
+                .map(|&x| x as char)
+                .collect();
+
+            let snippet = if snippet.len() > 40 {
+                format!("{}...", &snippet[..37])
+            } else {
+                snippet
+            };
+
+            format!("{}..{} {}", open, close + 1, snippet)
+        },
+        None => "?".to_string(),
+    }
+}
+
+impl Profile {
+    pub fn report(&self, code: &[u8], prog: &[IR], top: usize) {
+        let mut matcher = SpanMatcher::new(code);
+        let mut loops = Vec::new();
+        let mut mix = HashMap::new();
+        collect_loops(prog, self, &mut matcher, &mut loops, &mut mix);
+
+        let total: u64 = mix.values().sum::<u64>()
+            + self.iterations.values().sum::<u64>();
+
+        eprintln!("\nprofile: {} instructions and loop iterations executed", total);
+
+        let mut mix: Vec<_> = mix.into_iter().filter(|(_, n)| *n > 0).collect();
+        mix.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        for (kind, count) in mix.iter() {
+            eprintln!("  {:<8} {:>14}", kind, count);
+        }
+
+        loops.sort_by_key(|x| std::cmp::Reverse(x.iterations));
+
+        eprintln!("\nhottest loops:");
+        for (rank, stats) in loops.iter().take(top).enumerate() {
+            eprintln!(
+                "#{:<3} {:>14} iterations {:>10} entries  {}",
+                rank + 1,
+                stats.iterations,
+                stats.entries,
+                span_text(code, stats.span),
+            );
+
+            let mut lines = Vec::new();
+            write_code(std::slice::from_ref(stats.inst), 1, &mut lines);
+
+            for line in lines.iter().take(12) {
+                eprintln!("     {}", line);
+            }
+
+            if lines.len() > 12 {
+                eprintln!("     | ... {} more", lines.len() - 12);
+            }
+        }
+
+        eprintln!("\nunoptimized loops:");
+        for stats in loops.iter().filter(|x| x.innermost && x.iterations > 0).take(top) {
+            eprintln!(
+                "{:>14} iterations  {}: {}",
+                stats.iterations,
+                span_text(code, stats.span),
+                diagnose(stats.inst),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{empty, sink};
+
+    use crate::profiler::*;
+
+    #[test]
+    fn profile_counts_iterations() {
+        let code = b"++++[>+.<-]";
+        let prog = parse(code);
+        let mut state = State::default();
+        let mut profile = Profile::default();
+        eval_state(&prog, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut profile).unwrap();
+
+        let mut matcher = SpanMatcher::new(code);
+        let mut loops = Vec::new();
+        let mut mix = HashMap::new();
+        collect_loops(&prog, &profile, &mut matcher, &mut loops, &mut mix);
+
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].entries, 1);
+        assert_eq!(loops[0].iterations, 4);
+        assert_eq!(loops[0].span, Some((4, 10)));
+        assert_eq!(mix.get("out"), Some(&4));
+    }
+
+    #[test]
+    fn span_matcher_nested() {
+        let code = b"+[>[-]+[>,<-]<[.>]]";
+        let prog = parse(code);
+        let mut matcher = SpanMatcher::new(code);
+        let mut loops = Vec::new();
+        let mut mix = HashMap::new();
+        collect_loops(&prog, &Profile::default(), &mut matcher, &mut loops, &mut mix);
+
+        let spans: Vec<_> = loops.iter().map(|x| x.span).collect();
+        assert_eq!(spans, vec![Some((1, 18)), Some((7, 12)), Some((14, 17))]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::{BufRead, Write, Error, stdin, stdout};
+use std::collections::VecDeque;
+use std::fs::read;
+use std::iter::repeat_n;
+use std::num::Wrapping;
+
+use crate::internal::*;
+use crate::parser::*;
+use crate::evaluator::*;
+use crate::snapshot::*;
+
+const HELP: &str = "\
+:reset        clear the tape and move the pointer back to the start
+:tape         print all cells in use
+:ir [CODE]    show the generated code of CODE or the previous line
+:load FILE    run a file on the current tape
+:help         print this help message
+:quit         leave the repl";
+
+struct Tail<W: Write> {
+    inner: W,
+    last: Option<u8>,
+}
+
+impl<W: Write> Write for Tail<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let written = self.inner.write(buf)?;
+
+        if written > 0 {
+            self.last = Some(buf[written - 1]);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+fn fresh_state() -> State {
+    let mut tape = VecDeque::with_capacity(0x200);
+    tape.extend(repeat_n(Wrapping(0u8), 0x100));
+    State::new(tape, 0x40)
+}
+
+pub fn tape_window(state: &State, radius: isize) -> String {
+    let mut header = String::new();
+    let mut values = String::new();
+
+    for index in state.index - radius..=state.index + radius {
+        let val = state.tape.get(index as usize).map_or(0, |x| x.0);
+        let (open, close) = if index == state.index { ('[', ']') } else { (' ', ' ') };
+
+        header += &format!(" {:>4} ", index - state.origin);
+        values += &format!("{}{:>4}{}", open, val, close);
+    }
+
+    format!("{}\n{}", header.trim_end(), values.trim_end())
+}
+
+fn execute<R: BufRead>(code: &[u8], input: &mut R, state: &mut State) -> Result<Vec<IR>, Error> {
+    let prog = parse_snippet(code);
+    let mut output = Tail { inner: stdout(), last: None };
+
+    state.position.clear();
+    eval_state(&prog, input, &mut output, state, &Config::default(), &mut ())?;
+
+    if output.last.is_some_and(|x| x != b'\n') {
+        writeln!(output)?;
+    }
+
+    Ok(prog)
+}
+
+pub fn repl() -> Result<(), Error> {
+    let stdin = stdin();
+    let mut input = stdin.lock();
+    let mut state = fresh_state();
+    let mut last = Vec::new();
+    let mut pending = String::new();
+
+    println!("rbf repl, type :help for a list of commands");
+
+    loop {
+        print!("{}", if pending.is_empty() { "> " } else { ". " });
+        stdout().flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        if pending.is_empty() {
+            let mut split = line.trim().splitn(2, ' ');
+            let command = split.next().unwrap_or_default();
+            let arg = split.next().unwrap_or_default().trim();
+
+            match command {
+                ":quit" | ":q" => return Ok(()),
+                ":help" | ":h" => println!("{}", HELP),
+
+                ":reset" => {
+                    state = fresh_state();
+                    println!("{}", tape_window(&state, 4));
+                },
+
+                ":tape" => dump_tape(&state, &mut stdout())?,
+
+                ":ir" => if arg.is_empty() {
+                    show_code(&last, 120);
+                } else {
+                    show_code(&parse_snippet(arg.as_bytes()), 120);
+                },
+
+                ":load" => match read(arg) {
+                    Ok(code) if check_valid(&code) => {
+                        last = execute(&code, &mut input, &mut state)?;
+                        println!("{}", tape_window(&state, 4));
+                    },
+                    Ok(_) => println!("error: unbalanced brackets in {}", arg),
+                    Err(err) => println!("error: {}", err),
+                },
+
+                _ if command.starts_with(':') => {
+                    println!("error: unknown command {}, type :help for help", command);
+                },
+
+                _ => (),
+            }
+
+            if command.starts_with(':') {
+                continue;
+            }
+        }
+
+        pending += &line;
+
+        match match_brackets(pending.as_bytes()) {
+            Err(pos) if pending.as_bytes()[pos] == b'[' => continue,
+
+            Err(pos) => {
+                println!("error: unmatched ']' at column {}", pos + 1);
+            },
+
+            Ok(_) => {
+                last = execute(pending.as_bytes(), &mut input, &mut state)?;
+                println!("{}", tape_window(&state, 4));
+            },
+        }
+
+        pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{empty, sink};
+
+    use crate::repl::*;
+
+    #[test]
+    fn snippets_share_tape() {
+        let mut state = fresh_state();
+
+        for line in [&b"+++>++"[..], b"[<+>-]<", b"[>++<-]"].iter() {
+            let prog = parse_snippet(line);
+            state.position.clear();
+            eval_state(&prog, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut ()).unwrap();
+        }
+
+        assert_eq!(state.index - state.origin, 0);
+        assert_eq!(state.tape[state.index as usize + 1], Wrapping(10));
+    }
+
+    #[test]
+    fn tape_window_marks_pointer() {
+        let mut state = State::new(VecDeque::from(vec![Wrapping(0), Wrapping(7), Wrapping(3)]), 0);
+        state.index = 1;
+
+        let expected = concat!(
+            "    0     1     2\n",
+            "    0 [   7]    3",
+        );
+
+        assert_eq!(tape_window(&state, 1), expected);
+    }
+}
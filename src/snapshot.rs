@@ -0,0 +1,229 @@
+use std::io::{Read, Write, Error, ErrorKind};
+use std::collections::VecDeque;
+use std::iter::repeat_n;
+use std::num::Wrapping;
+
+use crate::internal::*;
+use crate::evaluator::*;
+
+const ROW: usize = 64;
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+// A saved position is only meaningful for the exact same program, so the
+// listing of the generated code is hashed to detect mismatches on load.
+pub fn fingerprint(prog: &[IR]) -> u64 {
+    let mut lines = Vec::new();
+    write_code(prog, 0, &mut lines);
+
+    let mut hash = 0xcbf29ce484222325u64;
+
+    for byte in lines.join("\n").bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+pub fn save<W: Write>(state: &State, prog: &[IR], output: &mut W) -> Result<(), Error> {
+    let position: Vec<String> = state.position.iter().map(usize::to_string).collect();
+
+    writeln!(output, "rbf state")?;
+    writeln!(output, "program {:016x}", fingerprint(prog))?;
+    writeln!(output, "index {}", state.index)?;
+    writeln!(output, "origin {}", state.origin)?;
+    writeln!(output, "position {}", position.join(" "))?;
+    writeln!(output, "consumed {}", state.consumed)?;
+    writeln!(output, "steps {}", state.steps)?;
+    writeln!(output, "tape {}", state.tape.len())?;
+
+    let cells: Vec<u8> = state.tape.iter().map(|x| x.0).collect();
+
+    for (row, chunk) in cells.chunks(ROW).enumerate() {
+        if chunk.iter().all(|&x| x == 0) {
+            continue;
+        }
+
+        let hex: String = chunk.iter().map(|x| format!("{:02x}", x)).collect();
+        writeln!(output, "row {} {}", row * ROW, hex)?;
+    }
+
+    Ok(())
+}
+
+pub fn load<R: Read>(input: &mut R, prog: &[IR]) -> Result<State, Error> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+
+    let mut lines = text.lines();
+    if lines.next() != Some("rbf state") {
+        return Err(invalid("not a state file"));
+    }
+
+    let mut state = State::default();
+
+    for line in lines {
+        let mut split = line.splitn(2, ' ');
+        let key = split.next().unwrap_or_default();
+        let value = split.next().unwrap_or_default();
+
+        let number = || value.parse::<i64>().map_err(|_| invalid("invalid number"));
+
+        match key {
+            "program" => if value != format!("{:016x}", fingerprint(prog)) {
+                return Err(invalid("state was saved for a different program"));
+            },
+
+            "index" => state.index = number()? as isize,
+            "origin" => state.origin = number()? as isize,
+            "consumed" => state.consumed = number()? as u64,
+            "steps" => state.steps = number()? as u64,
+
+            "position" => {
+                state.position = value.split_whitespace()
+                    .map(|x| x.parse().map_err(|_| invalid("invalid position")))
+                    .collect::<Result<_, _>>()?;
+            },
+
+            "tape" => {
+                state.tape = VecDeque::new();
+                state.tape.extend(repeat_n(Wrapping(0u8), number()? as usize));
+            },
+
+            "row" => {
+                let mut split = value.splitn(2, ' ');
+                let offset: usize = split.next().unwrap_or_default()
+                    .parse().map_err(|_| invalid("invalid row offset"))?;
+                let hex = split.next().unwrap_or_default().as_bytes();
+
+                for (k, pair) in hex.chunks(2).enumerate() {
+                    let pair = std::str::from_utf8(pair).map_err(|_| invalid("invalid row"))?;
+                    let val = u8::from_str_radix(pair, 16).map_err(|_| invalid("invalid row"))?;
+                    let cell = state.tape.get_mut(offset + k).ok_or_else(|| invalid("row out of range"))?;
+                    *cell = Wrapping(val);
+                }
+            },
+
+            "" => (),
+            _ => return Err(invalid("unknown key in state file")),
+        }
+    }
+
+    Ok(state)
+}
+
+pub fn dump_tape<W: Write>(state: &State, output: &mut W) -> Result<(), Error> {
+    let used: Vec<isize> = (0..state.tape.len() as isize)
+        .filter(|&x| state.tape[x as usize].0 != 0)
+        .collect();
+
+    let first = used.first().copied().unwrap_or(state.origin).min(state.origin);
+    let last = used.last().copied().unwrap_or(state.origin).max(state.index);
+
+    writeln!(output, "# pointer {:+}", state.index - state.origin)?;
+
+    if first < state.origin {
+        writeln!(output, "@{}", first - state.origin)?;
+    }
+
+    let cells: Vec<String> = (first..=last)
+        .map(|x| state.tape.get(x as usize).map_or(0, |x| x.0))
+        .map(|x| format!("{:>3}", x))
+        .collect();
+
+    for chunk in cells.chunks(16) {
+        writeln!(output, "{}", chunk.join(" "))?;
+    }
+
+    Ok(())
+}
+
+pub fn init_tape(state: &mut State, text: &str) -> Result<(), Error> {
+    let mut offset = 0isize;
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+
+        for token in line.split(|x: char| x.is_whitespace() || x == ',') {
+            if token.is_empty() {
+                continue;
+            }
+
+            if let Some(jump) = token.strip_prefix('@') {
+                offset = jump.parse().map_err(|_| invalid("invalid offset"))?;
+                continue;
+            }
+
+            let val: u8 = token.parse().map_err(|_| invalid("invalid cell value"))?;
+            let mut index = state.index;
+            let relative = state.origin - state.index + offset;
+            let target = touch_cell(state, &mut index, relative);
+            state.index = index;
+
+            state.tape[target as usize] = Wrapping(val);
+            offset += 1;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{empty, sink};
+
+    use crate::parser::*;
+    use crate::snapshot::*;
+
+    #[test]
+    fn save_and_resume() {
+        let code = b"++++++++[>++++++++<-]>+>+++[<.+>-]";
+        let prog = parse(code);
+
+        let mut state = State::new(VecDeque::new(), 0);
+        let mut output = Vec::new();
+        let config = Config { limit: 1, ..Config::default() };
+        let done = eval_state(&prog, &mut empty(), &mut output, &mut state, &config, &mut ()).unwrap();
+        assert!(!done);
+        assert_eq!(output, b"A");
+
+        let mut file = Vec::new();
+        save(&state, &prog, &mut file).unwrap();
+        let mut state = load(&mut file.as_slice(), &prog).unwrap();
+
+        let done = eval_state(&prog, &mut empty(), &mut output, &mut state, &Config::default(), &mut ()).unwrap();
+        assert!(done);
+        assert_eq!(output, b"ABC");
+    }
+
+    #[test]
+    fn load_other_program() {
+        let prog = parse(b"+[>+<-]");
+        let mut file = Vec::new();
+        save(&State::default(), &prog, &mut file).unwrap();
+
+        let other = parse(b"+[>++<-]");
+        assert!(load(&mut file.as_slice(), &other).is_err());
+    }
+
+    #[test]
+    fn dump_and_init_tape() {
+        let prog = parse(b"<<+>>>++>+++<<<");
+        let mut state = State::new(VecDeque::new(), 0);
+        eval_state(&prog, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut ()).unwrap();
+
+        let mut dump = Vec::new();
+        dump_tape(&state, &mut dump).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert_eq!(dump, "# pointer +0\n@-2\n  1   0   0   2   3\n");
+
+        let mut fresh = State::new(VecDeque::new(), 0);
+        init_tape(&mut fresh, &dump).unwrap();
+        let cells: Vec<u8> = fresh.tape.iter().map(|x| x.0).collect();
+        assert_eq!(cells, vec![1, 0, 0, 2, 3]);
+        assert_eq!(fresh.index - fresh.origin, 0);
+    }
+}
@@ -0,0 +1,457 @@
+use std::io::{Read, Write, Error, ErrorKind, Cursor, empty, stdin};
+use std::fs::File;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Char,
+    Decimal,
+    Hex,
+    Utf8,
+}
+
+// What to do with input characters which are not valid UTF-8 or do not fit
+// into a cell.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Invalid {
+    Replace,
+    Skip,
+    Error,
+}
+
+impl Invalid {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "replace" => Some(Invalid::Replace),
+            "skip" => Some(Invalid::Skip),
+            "error" => Some(Invalid::Error),
+            _ => None,
+        }
+    }
+}
+
+impl Mode {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "char" => Some(Mode::Char),
+            "decimal" => Some(Mode::Decimal),
+            "hex" => Some(Mode::Hex),
+            "utf8" => Some(Mode::Utf8),
+            _ => None,
+        }
+    }
+
+    pub fn radix(self) -> u32 {
+        match self {
+            Mode::Hex => 16,
+            _ => 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Format {
+    pub input: Mode,
+    pub output: Mode,
+    pub invalid: Invalid,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format {
+            input: Mode::Char,
+            output: Mode::Char,
+            invalid: Invalid::Error,
+        }
+    }
+}
+
+// Writes every byte as a number followed by a separator, or as the UTF-8
+// encoding of the code point with the same value.
+pub struct CellWriter<W: Write> {
+    inner: W,
+    mode: Mode,
+}
+
+impl<W: Write> CellWriter<W> {
+    pub fn new(inner: W, mode: Mode) -> Self {
+        CellWriter { inner, mode }
+    }
+}
+
+impl<W: Write> Write for CellWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let mut text = String::with_capacity(buf.len() * 4);
+
+        for byte in buf.iter() {
+            match self.mode {
+                Mode::Hex => text += &format!("{:02x} ", byte),
+                Mode::Utf8 => text.push(*byte as char),
+                _ => text += &format!("{} ", byte),
+            }
+        }
+
+        self.inner.write_all(text.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+// Splits raw input into bytes, UTF-8 characters or numbers.
+pub struct Scanner<R: Read> {
+    inner: R,
+    pending: Option<u8>,
+}
+
+impl<R: Read> Scanner<R> {
+    pub fn new(inner: R) -> Self {
+        Scanner { inner, pending: None }
+    }
+
+    pub fn next_byte(&mut self) -> Result<Option<u8>, Error> {
+        if let Some(byte) = self.pending.take() {
+            return Ok(Some(byte));
+        }
+
+        let mut byte = [0u8];
+
+        match self.inner.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Malformed sequences are returned as U+FFFD.
+    pub fn next_char(&mut self) -> Result<Option<char>, Error> {
+        let lead = match self.next_byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+
+        let len = match lead {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 0,
+        };
+
+        let mut bytes = vec![lead];
+
+        while bytes.len() < len {
+            match self.next_byte()? {
+                Some(byte @ 0x80..=0xbf) => bytes.push(byte),
+
+                // keep the start of the next character for later
+                other => {
+                    self.pending = other;
+                    break;
+                },
+            }
+        }
+
+        let decoded = std::str::from_utf8(&bytes).ok().and_then(|x| x.chars().next());
+        Ok(Some(decoded.unwrap_or(char::REPLACEMENT_CHARACTER)))
+    }
+
+    // Returns the digits of the next whitespace or comma separated number,
+    // including its sign.
+    pub fn next_number(&mut self, radix: u32) -> Result<Option<String>, Error> {
+        let mut number = String::new();
+
+        loop {
+            match self.next_byte()? {
+                None => return Ok(None),
+                Some(b'-') => number = "-".to_string(),
+                Some(byte) if byte.is_ascii_whitespace() || byte == b',' => number.clear(),
+                Some(byte) if (byte as char).is_digit(radix) => {
+                    number.push(byte as char);
+                    break;
+                },
+
+                Some(byte) => return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unexpected character {:?} in numeric input", byte as char)
+                )),
+            }
+        }
+
+        while let Some(byte) = self.next_byte()? {
+            // the byte may start the next number, like the sign in `12-5`
+            if !(byte as char).is_digit(radix) {
+                self.pending = Some(byte);
+                break;
+            }
+
+            number.push(byte as char);
+        }
+
+        Ok(Some(number))
+    }
+}
+
+// Parses whitespace or comma separated numbers and returns each of them as
+// a single byte, wrapping around like the cells do. In utf8 mode, every
+// character is returned as its code point instead.
+pub struct CellReader<R: Read> {
+    scanner: Scanner<R>,
+    mode: Mode,
+    invalid: Invalid,
+}
+
+impl<R: Read> CellReader<R> {
+    pub fn new(inner: R, mode: Mode, invalid: Invalid) -> Self {
+        CellReader { scanner: Scanner::new(inner), mode, invalid }
+    }
+
+    fn next_number(&mut self) -> Result<Option<u8>, Error> {
+        let radix = self.mode.radix();
+
+        let number = match self.scanner.next_number(radix)? {
+            Some(number) => number,
+            None => return Ok(None),
+        };
+
+        let value = number.trim_start_matches('-').chars()
+            .filter_map(|x| x.to_digit(radix))
+            .fold(0u32, |acc, x| acc.wrapping_mul(radix).wrapping_add(x)) as u8;
+
+        Ok(Some(if number.starts_with('-') { value.wrapping_neg() } else { value }))
+    }
+
+    fn next_char(&mut self) -> Result<Option<u8>, Error> {
+        while let Some(chr) = self.scanner.next_char()? {
+            if (chr as u32) < 0x100 {
+                return Ok(Some(chr as u32 as u8));
+            }
+
+            match self.invalid {
+                Invalid::Replace => return Ok(Some(b'?')),
+                Invalid::Skip => continue,
+                Invalid::Error => return Err(invalid_char(chr)),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+pub fn invalid_char(chr: char) -> Error {
+    let msg = match chr {
+        char::REPLACEMENT_CHARACTER => "invalid utf-8 in input".to_string(),
+        _ => format!("character {:?} does not fit into a cell", chr),
+    };
+
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl<R: Read> Read for CellReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut count = 0;
+
+        for slot in buf.iter_mut() {
+            let next = match self.mode {
+                Mode::Char => self.scanner.next_byte()?,
+                Mode::Utf8 => self.next_char()?,
+                _ => self.next_number()?,
+            };
+
+            match next {
+                Some(value) => *slot = value,
+                None => break,
+            }
+
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+// Keeps a copy of everything that was read, so a run can be replayed.
+pub struct Tee<R: Read> {
+    inner: R,
+    pub copy: Vec<u8>,
+}
+
+impl<R: Read> Tee<R> {
+    pub fn new(inner: R) -> Self {
+        Tee { inner, copy: Vec::new() }
+    }
+}
+
+impl<R: Read> Read for Tee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let count = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..count]);
+        Ok(count)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Source {
+    Text(Vec<u8>),
+    File(String),
+    Stdin,
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|x| x as u8)
+}
+
+pub fn decode_escapes(text: &str) -> Result<Vec<u8>, Error> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while let Some(&byte) = bytes.get(index) {
+        index += 1;
+
+        if byte != b'\\' {
+            out.push(byte);
+            continue;
+        }
+
+        let escape = bytes.get(index).copied();
+        index += 1;
+
+        match escape {
+            Some(b'n') => out.push(b'\n'),
+            Some(b'r') => out.push(b'\r'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'0') => out.push(0),
+            Some(b'\\') => out.push(b'\\'),
+
+            Some(b'x') => {
+                let high = bytes.get(index).copied().and_then(hex_digit);
+                let low = bytes.get(index + 1).copied().and_then(hex_digit);
+
+                match (high, low) {
+                    (Some(high), Some(low)) => out.push(high << 4 | low),
+                    _ => return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "expected two hex digits after \\x"
+                    )),
+                }
+
+                index += 2;
+            },
+
+            _ => return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown escape sequence at position {}", index - 2)
+            )),
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn open_inputs(sources: Vec<Source>) -> Result<Box<dyn Read>, Error> {
+    let mut input: Box<dyn Read> = Box::new(empty());
+
+    for source in sources.into_iter().rev() {
+        let head: Box<dyn Read> = match source {
+            Source::Text(text) => Box::new(Cursor::new(text)),
+            Source::File(path) => Box::new(File::open(path)?),
+            Source::Stdin => Box::new(stdin().lock()),
+        };
+
+        input = Box::new(head.chain(input));
+    }
+
+    Ok(input)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::streams::*;
+
+    #[test]
+    fn decode_escapes_simple() {
+        assert_eq!(decode_escapes("a\\nb").unwrap(), b"a\nb");
+        assert_eq!(decode_escapes("\\0\\xFF\\x7f").unwrap(), vec![0, 255, 127]);
+        assert_eq!(decode_escapes("\\\\n").unwrap(), b"\\n");
+        assert!(decode_escapes("\\x4").is_err());
+        assert!(decode_escapes("\\q").is_err());
+        assert!(decode_escapes("\\").is_err());
+    }
+
+    #[test]
+    fn numeric_writer_modes() {
+        let mut output = Vec::new();
+        CellWriter::new(&mut output, Mode::Decimal).write_all(&[72, 0, 255]).unwrap();
+        assert_eq!(output, b"72 0 255 ");
+
+        let mut output = Vec::new();
+        CellWriter::new(&mut output, Mode::Hex).write_all(&[72, 0, 255]).unwrap();
+        assert_eq!(output, b"48 00 ff ");
+    }
+
+    #[test]
+    fn numeric_reader_modes() {
+        let mut values = Vec::new();
+        let mut input = CellReader::new(&b" 12, 300\n-1 7"[..], Mode::Decimal, Invalid::Error);
+        input.read_to_end(&mut values).unwrap();
+        assert_eq!(values, vec![12, 44, 255, 7]);
+
+        let mut values = Vec::new();
+        let mut input = CellReader::new(&b"ff 1A"[..], Mode::Hex, Invalid::Error);
+        input.read_to_end(&mut values).unwrap();
+        assert_eq!(values, vec![255, 26]);
+
+        let mut values = Vec::new();
+        let mut input = CellReader::new(&b"12 x"[..], Mode::Decimal, Invalid::Error);
+        assert!(input.read_to_end(&mut values).is_err());
+    }
+
+    #[test]
+    fn numeric_reader_signs() {
+        let mut values = Vec::new();
+        let mut input = CellReader::new(&b"12-5-1,3"[..], Mode::Decimal, Invalid::Error);
+        input.read_to_end(&mut values).unwrap();
+        assert_eq!(values, vec![12, 251, 255, 3]);
+
+        let mut values = Vec::new();
+        let mut input = CellReader::new(&b"a-b"[..], Mode::Hex, Invalid::Error);
+        input.read_to_end(&mut values).unwrap();
+        assert_eq!(values, vec![10, 245]);
+    }
+
+    #[test]
+    fn utf8_modes() {
+        let mut output = Vec::new();
+        CellWriter::new(&mut output, Mode::Utf8).write_all(&[b'a', 0xe9, 0xff]).unwrap();
+        assert_eq!(output, "a\u{e9}\u{ff}".as_bytes());
+
+        let text = "a\u{e9}\u{20ac}b".as_bytes();
+
+        let mut values = Vec::new();
+        CellReader::new(text, Mode::Utf8, Invalid::Replace).read_to_end(&mut values).unwrap();
+        assert_eq!(values, vec![b'a', 0xe9, b'?', b'b']);
+
+        let mut values = Vec::new();
+        CellReader::new(&[b'a', 0xff, 0xc3, b'b'][..], Mode::Utf8, Invalid::Skip).read_to_end(&mut values).unwrap();
+        assert_eq!(values, vec![b'a', b'b']);
+
+        let mut values = Vec::new();
+        let mut input = CellReader::new(text, Mode::Utf8, Format::default().invalid);
+        let err = input.read_to_end(&mut values).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn open_inputs_chain() {
+        let sources = vec![
+            Source::Text(b"ab".to_vec()),
+            Source::Text(Vec::new()),
+            Source::Text(b"c".to_vec()),
+        ];
+
+        let mut text = String::new();
+        open_inputs(sources).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "abc");
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::Error;
+
+pub struct RawMode {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io::Error;
+    use std::panic::{set_hook, take_hook};
+    use std::ptr::null_mut;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+    use std::sync::Once;
+
+    // The saved settings have to be reachable from the signal handler,
+    // so they live behind an atomic pointer instead of a lock.
+    static SAVED: AtomicPtr<libc::termios> = AtomicPtr::new(null_mut());
+    static HOOKS: Once = Once::new();
+
+    pub fn restore_saved() {
+        let saved = SAVED.load(Ordering::SeqCst);
+
+        if !saved.is_null() {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+        }
+    }
+
+    extern "C" fn on_signal(signal: libc::c_int) {
+        restore_saved();
+
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    fn install_hooks() {
+        HOOKS.call_once(|| {
+            let previous = take_hook();
+            set_hook(Box::new(move |info| {
+                restore_saved();
+                previous(info);
+            }));
+
+            let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+
+            for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGQUIT].iter() {
+                unsafe { libc::signal(*signal, handler) };
+            }
+        });
+    }
+
+    pub fn is_terminal() -> bool {
+        unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+    }
+
+    pub fn enable(translate_enter: bool) -> Result<libc::termios, Error> {
+        let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let saved = Box::into_raw(Box::new(original));
+        let old = SAVED.swap(saved, Ordering::SeqCst);
+
+        if !old.is_null() {
+            drop(unsafe { Box::from_raw(old) });
+        }
+
+        install_hooks();
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        if !translate_enter {
+            raw.c_iflag &= !libc::ICRNL;
+        }
+
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(original)
+    }
+
+    pub fn disable(original: &libc::termios) {
+        let saved = SAVED.swap(null_mut(), Ordering::SeqCst);
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+
+        if !saved.is_null() {
+            drop(unsafe { Box::from_raw(saved) });
+        }
+    }
+}
+
+impl RawMode {
+    // Puts the terminal attached to stdin into non-canonical mode without
+    // echo, so every key press is delivered immediately. Returns `None` if
+    // stdin is not a terminal, in which case there is nothing to do.
+    #[cfg(unix)]
+    pub fn enable(translate_enter: bool) -> Result<Option<RawMode>, Error> {
+        if !unix::is_terminal() {
+            return Ok(None);
+        }
+
+        let original = unix::enable(translate_enter)?;
+        Ok(Some(RawMode { original }))
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable(_translate_enter: bool) -> Result<Option<RawMode>, Error> {
+        Err(Error::other("raw input is only supported on unix"))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unix::disable(&self.original);
+    }
+}
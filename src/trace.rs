@@ -0,0 +1,266 @@
+use std::io::{Write, Error};
+
+use crate::internal::*;
+use crate::evaluator::*;
+use crate::naive::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    All,
+    Io,
+    Loops,
+    Range(usize, usize),
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "all" => Some(Filter::All),
+            "io" => Some(Filter::Io),
+            "loops" => Some(Filter::Loops),
+            _ => {
+                let mut split = text.splitn(2, "..");
+                let start = split.next()?.parse().ok()?;
+                let end = split.next()?.parse().ok()?;
+                Some(Filter::Range(start, end))
+            },
+        }
+    }
+}
+
+pub struct Tracer<W: Write> {
+    output: W,
+    filter: Filter,
+    step: u64,
+    error: Option<Error>,
+}
+
+fn affected(inst: &IR) -> Option<isize> {
+    match inst {
+        IR::Set(off, _) | IR::Add(off, _) | IR::Mul(off, _) |
+        IR::Store(off) | IR::Count(off, _) | IR::Input(off) | IR::Output(off) |
+        IR::FixedLoop(_, off, _, _) | IR::If(_, off, _, _) => Some(*off),
+
+        IR::Loop(_) | IR::Scan(_, _) | IR::Fill(_, _, _) | IR::Walk(_, _) => Some(0),
+
+        _ => None,
+    }
+}
+
+// Quotes and backslashes in the text of an instruction, like the string of
+// a print, would otherwise end the json string early.
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            c if c.is_control() => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, filter: Filter) -> Self {
+        Tracer {
+            output,
+            filter,
+            step: 0,
+            error: None,
+        }
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.output.flush(),
+        }
+    }
+
+    fn record(
+        &mut self,
+        kind: &str,
+        what: &str,
+        pos: Option<usize>,
+        cell: Option<isize>,
+        state: &State
+    ) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let mut line = format!("{{\"step\":{},\"kind\":\"{}\",", self.step, escape_json(kind));
+
+        if let Some(pos) = pos {
+            line += &format!("\"pos\":{},", pos);
+        }
+
+        let ptr = state.index - state.origin;
+        line += &format!("\"inst\":\"{}\",\"ptr\":{},\"cells\":{{", escape_json(what), ptr);
+
+        if let Some(off) = cell {
+            let val = state.tape.get((state.index + off) as usize).map_or(0, |x| x.0);
+            line += &format!("\"{}\":{}", ptr + off, val);
+        }
+
+        line += "}}\n";
+
+        if let Err(err) = self.output.write_all(line.as_bytes()) {
+            self.error = Some(err);
+        }
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn inst(&mut self, inst: &IR, state: &State) {
+        self.step += 1;
+
+        let keep = match (self.filter, inst) {
+            (Filter::All, IR::Start) => false,
+            (Filter::All, _) => true,
+            (Filter::Io, IR::Input(_)) | (Filter::Io, IR::Output(_)) |
+            (Filter::Io, IR::Print(_)) => true,
+            (Filter::Loops, IR::Loop(_)) | (Filter::Loops, IR::FixedLoop(_, _, _, _)) |
+            (Filter::Loops, IR::If(_, _, _, _)) |
+            (Filter::Loops, IR::Scan(_, _)) | (Filter::Loops, IR::Fill(_, _, _)) |
+            (Filter::Loops, IR::Walk(_, _)) => true,
+            _ => false,
+        };
+
+        if keep {
+            self.record("inst", &inst.to_string(), None, affected(inst), state);
+        }
+    }
+
+    fn iteration(&mut self, inst: &IR, state: &State) {
+        self.step += 1;
+
+        if let Filter::All | Filter::Loops = self.filter {
+            self.record("iter", &inst.to_string(), None, affected(inst), state);
+        }
+    }
+}
+
+pub struct SourceTracer<'a, W: Write> {
+    tracer: Tracer<W>,
+    code: &'a [u8],
+}
+
+impl<'a, W: Write> SourceTracer<'a, W> {
+    pub fn new(output: W, filter: Filter, code: &'a [u8]) -> Self {
+        SourceTracer {
+            tracer: Tracer::new(output, filter),
+            code,
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        self.tracer.finish()
+    }
+}
+
+impl<'a, W: Write> SourceObserver for SourceTracer<'a, W> {
+    fn command(&mut self, pos: usize, state: &State) {
+        self.tracer.step += 1;
+
+        let byte = self.code[pos];
+        let keep = match self.tracer.filter {
+            Filter::All => true,
+            Filter::Io => byte == b',' || byte == b'.',
+            Filter::Loops => byte == b'[' || byte == b']',
+            Filter::Range(start, end) => pos >= start && pos < end,
+        };
+
+        if keep {
+            let what = (byte as char).to_string();
+            self.tracer.record("cmd", &what, Some(pos), Some(0), state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{empty, sink};
+
+    use crate::parser::*;
+    use crate::prefix::*;
+    use crate::trace::*;
+
+    #[test]
+    fn filter_parse() {
+        assert_eq!(Filter::parse("io"), Some(Filter::Io));
+        assert_eq!(Filter::parse("loops"), Some(Filter::Loops));
+        assert_eq!(Filter::parse("3..17"), Some(Filter::Range(3, 17)));
+        assert_eq!(Filter::parse("3.."), None);
+        assert_eq!(Filter::parse("bogus"), None);
+    }
+
+    #[test]
+    fn trace_ir_io() {
+        let code = b"+++[>++<-]>.";
+        let prog = parse(code);
+        let mut state = State::default();
+        let mut output = Vec::new();
+        let mut tracer = Tracer::new(&mut output, Filter::Io);
+        eval_state(&prog, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut tracer).unwrap();
+        tracer.finish().unwrap();
+
+        let expected = "{\"step\":5,\"kind\":\"inst\",\"inst\":\"out +1\",\"ptr\":0,\"cells\":{\"1\":6}}\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn trace_loop_counter() {
+        let code = b">++[.-]";
+        let prog = parse(code);
+        let mut state = State::default();
+        let mut output = Vec::new();
+        let mut tracer = Tracer::new(&mut output, Filter::Loops);
+        eval_state(&prog, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut tracer).unwrap();
+        tracer.finish().unwrap();
+
+        let expected = concat!(
+            "{\"step\":4,\"kind\":\"iter\",\"inst\":\"loop +1 +1 +1 (fix)\",\"ptr\":0,\"cells\":{\"1\":2}}\n",
+            "{\"step\":7,\"kind\":\"iter\",\"inst\":\"loop +1 +1 +1 (fix)\",\"ptr\":0,\"cells\":{\"1\":1}}\n",
+            "{\"step\":10,\"kind\":\"inst\",\"inst\":\"loop +1 +1 +1 (fix)\",\"ptr\":0,\"cells\":{\"1\":0}}\n",
+        );
+
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn trace_print() {
+        let code = [&[b'+'; 10][..], b".", &[b'+'; 24][..], b"."].concat();
+        let mut prog = parse(&code);
+        evaluate_prefix(&mut prog, &Config::default());
+        let mut state = State::default();
+        let mut output = Vec::new();
+        let mut tracer = Tracer::new(&mut output, Filter::Io);
+        eval_state(&prog, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut tracer).unwrap();
+        tracer.finish().unwrap();
+
+        let expected = "{\"step\":2,\"kind\":\"inst\",\"inst\":\"print \\\"\\\\n\\\\\\\"\\\"\",\"ptr\":0,\"cells\":{}}\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn trace_source_range() {
+        let code = b"++[>+<-]";
+        let mut state = State::default();
+        let mut output = Vec::new();
+        let mut tracer = SourceTracer::new(&mut output, Filter::Range(4, 5), code);
+        run(code, &mut empty(), &mut sink(), &mut state, &Config::default(), &mut tracer).unwrap();
+        tracer.finish().unwrap();
+
+        let expected = concat!(
+            "{\"step\":5,\"kind\":\"cmd\",\"pos\":4,\"inst\":\"+\",\"ptr\":1,\"cells\":{\"1\":1}}\n",
+            "{\"step\":10,\"kind\":\"cmd\",\"pos\":4,\"inst\":\"+\",\"ptr\":1,\"cells\":{\"1\":2}}\n",
+        );
+
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}